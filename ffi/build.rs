@@ -0,0 +1,15 @@
+// Generates C header of the API into include/glb_rs.h.
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let config = cbindgen::Config::from_file("cbindgen.toml")
+        .expect("cbindgen.toml can't be read");
+
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src("src/lib.rs")
+        .generate()
+        .expect("C header can't be generated")
+        .write_to_file("include/glb_rs.h");
+}
@@ -0,0 +1,159 @@
+#ifndef GLB_RS_H
+#define GLB_RS_H
+
+/* Generated by cbindgen from ffi/src/lib.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Whether the file is stored encrypted in the archive.
+typedef enum GlbFlag {
+  GlbFlag_Normal,
+  GlbFlag_Encrypted,
+} GlbFlag;
+
+// Archive opened by `glb_archive_open` or `glb_archive_from_bytes`.
+typedef struct GlbArchive GlbArchive;
+
+// Palette read by `glb_archive_read_palette`.
+typedef struct GlbPalette GlbPalette;
+
+// FAT entry of single file.
+typedef struct GlbEntryInfo {
+  enum GlbFlag flag;
+  // Position of the file in the archive.
+  uint32_t offset;
+  // Size of the file in bytes.
+  uint32_t length;
+} GlbEntryInfo;
+
+// Bytes owned by the caller, release them with `glb_buffer_free`.
+// Data is NULL if the function returning the buffer failed.
+typedef struct GlbBuffer {
+  uint8_t *data;
+  size_t len;
+} GlbBuffer;
+
+// Picture decoded by `glb_archive_decode_pic`, four bytes per pixel
+// in order red, green, blue and alpha, row by row. Transparent pixels
+// have alpha 0. Release the pixels with `glb_buffer_free`.
+typedef struct GlbImage {
+  uint32_t width;
+  uint32_t height;
+  struct GlbBuffer rgba;
+} GlbImage;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message describing the last failure on the calling thread, or NULL if nothing failed yet.
+// The string is valid until the next failing call on the same thread.
+const char *glb_last_error(void);
+
+// Reads archive from file and parses its FAT.
+// Returns NULL if the file can't be read or FAT is broken.
+//
+// # Safety
+//
+// `path` has to be NUL terminated string.
+struct GlbArchive *glb_archive_open(const char *path);
+
+// Copies archive from memory and parses its FAT.
+// Returns NULL if FAT is broken.
+//
+// # Safety
+//
+// `data` has to point to `len` readable bytes.
+struct GlbArchive *glb_archive_from_bytes(const uint8_t *data, size_t len);
+
+// Releases the archive. Names returned by `glb_archive_entry_name` are released with it.
+//
+// # Safety
+//
+// `archive` has to be NULL or returned by one of the open functions, and not freed yet.
+void glb_archive_free(struct GlbArchive *archive);
+
+// Number of files in FAT, header not included.
+//
+// # Safety
+//
+// `archive` has to be NULL or valid archive.
+size_t glb_archive_entry_count(const struct GlbArchive *archive);
+
+// Filename of the entry at given position of FAT, which may be empty.
+// The string is owned by the archive. Returns NULL if index is out of range.
+//
+// # Safety
+//
+// `archive` has to be NULL or valid archive.
+const char *glb_archive_entry_name(const struct GlbArchive *archive, size_t index);
+
+// Fills `info` with flag, offset and length of the entry at given position of FAT.
+// Returns false if index is out of range.
+//
+// # Safety
+//
+// `archive` has to be NULL or valid archive, `info` has to be NULL or writable.
+bool glb_archive_entry_info(const struct GlbArchive *archive,
+                            size_t index,
+                            struct GlbEntryInfo *info);
+
+// Finds position of the first entry with given filename and stores it into `index`.
+// Returns false if there is no such entry.
+//
+// # Safety
+//
+// `archive` has to be NULL or valid archive, `filename` has to be NULL
+// or NUL terminated string and `index` has to be NULL or writable.
+bool glb_archive_find_entry(const struct GlbArchive *archive, const char *filename, size_t *index);
+
+// Reads the file at given position of FAT, decrypted if it's encrypted.
+//
+// # Safety
+//
+// `archive` has to be NULL or valid archive.
+struct GlbBuffer glb_archive_read_entry(const struct GlbArchive *archive, size_t index);
+
+// Releases bytes returned by `glb_archive_read_entry` or `glb_archive_decode_pic`.
+//
+// # Safety
+//
+// `buffer` has to be returned by one of those functions and not freed yet.
+void glb_buffer_free(struct GlbBuffer buffer);
+
+// Decodes the file at given position of FAT as VGA palette, such as PALETTE_DAT.
+// Returns NULL if the file isn't palette.
+//
+// # Safety
+//
+// `archive` has to be NULL or valid archive.
+struct GlbPalette *glb_archive_read_palette(const struct GlbArchive *archive, size_t index);
+
+// Releases palette returned by `glb_archive_read_palette`.
+//
+// # Safety
+//
+// `palette` has to be NULL or returned by `glb_archive_read_palette` and not freed yet.
+void glb_palette_free(struct GlbPalette *palette);
+
+// Decodes the file at given position of FAT as PIC and converts it to RGBA
+// with given palette. Pixels of returned image are NULL if the file isn't
+// picture, its pixels don't fill its width and height, or it uses colors
+// the palette doesn't have.
+//
+// # Safety
+//
+// `archive` and `palette` have to be NULL or valid.
+struct GlbImage glb_archive_decode_pic(const struct GlbArchive *archive,
+                                       size_t index,
+                                       const struct GlbPalette *palette);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* GLB_RS_H */
@@ -0,0 +1,414 @@
+//! C API of glb-rs, the header is generated into include/glb_rs.h.
+//!
+//! Archives, palettes and buffers returned by the functions are owned by the caller
+//! and have to be released with the matching free function. Strings returned
+//! by the functions are borrowed and must not be freed.
+//!
+//! Functions that fail return NULL, false or empty buffer,
+//! `glb_last_error` then describes what went wrong. Panics are caught
+//! and reported the same way, they never unwind into the caller.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use glb_rs::{FileAllocationTable, Flag, GlbError, Palette, UntypedFile};
+
+/// Archive opened by `glb_archive_open` or `glb_archive_from_bytes`.
+pub struct GlbArchive {
+    archive: glb_rs::GlbArchive,
+    fat: FileAllocationTable,
+
+    // Filenames with NUL terminator, so they can be handed out as C strings.
+    names: Vec<CString>,
+}
+
+/// Palette read by `glb_archive_read_palette`.
+pub struct GlbPalette {
+    palette: Palette,
+}
+
+/// Whether the file is stored encrypted in the archive.
+#[repr(C)]
+pub enum GlbFlag {
+    Normal,
+    Encrypted,
+}
+
+/// FAT entry of single file.
+#[repr(C)]
+pub struct GlbEntryInfo {
+    pub flag: GlbFlag,
+
+    /// Position of the file in the archive.
+    pub offset: u32,
+
+    /// Size of the file in bytes.
+    pub length: u32,
+}
+
+/// Bytes owned by the caller, release them with `glb_buffer_free`.
+/// Data is NULL if the function returning the buffer failed.
+#[repr(C)]
+pub struct GlbBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+/// Picture decoded by `glb_archive_decode_pic`, four bytes per pixel
+/// in order red, green, blue and alpha, row by row. Transparent pixels
+/// have alpha 0. Release the pixels with `glb_buffer_free`.
+#[repr(C)]
+pub struct GlbImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: GlbBuffer,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: String) {
+    // Messages don't contain NUL, but if they did they'd be cut there rather than lost.
+    let message = CString::new(message).unwrap_or_else(|e| {
+        let nul = e.nul_position();
+        CString::new(&e.into_vec()[..nul]).unwrap_or_default()
+    });
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn fail<T>(message: String, value: T) -> T {
+    set_error(message);
+    value
+}
+
+// Runs body of exported function, panic is turned into failure
+// since it must not unwind into the caller.
+fn guard<T>(failed: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|m| m.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_owned());
+        fail(format!("internal error: {}", message), failed)
+    })
+}
+
+impl GlbBuffer {
+    fn new(bytes: Vec<u8>) -> GlbBuffer {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        GlbBuffer { data, len }
+    }
+
+    fn null() -> GlbBuffer {
+        GlbBuffer { data: ptr::null_mut(), len: 0 }
+    }
+}
+
+impl GlbImage {
+    fn null() -> GlbImage {
+        GlbImage { width: 0, height: 0, rgba: GlbBuffer::null() }
+    }
+}
+
+impl GlbArchive {
+    fn new(archive: glb_rs::GlbArchive) -> Result<GlbArchive, GlbError> {
+        let fat = archive.parse_fat()?;
+
+        // Filenames are read up to the first NUL, so they can't contain any.
+        let names = fat.entries.iter()
+            .map(|e| CString::new(e.filename.as_str()).unwrap_or_default())
+            .collect();
+
+        Ok(GlbArchive { archive, fat, names })
+    }
+
+    fn read(&self, index: usize) -> Result<UntypedFile<'_>, String> {
+        let entry = self.fat.entries.get(index)
+            .ok_or_else(|| format!("entry {} is out of range, archive has {} entries", index, self.fat.entries.len()))?;
+        UntypedFile::read_file(&self.archive, entry).map_err(|e| e.to_string())
+    }
+}
+
+fn into_handle(archive: Result<GlbArchive, GlbError>) -> *mut GlbArchive {
+    match archive {
+        Ok(archive) => Box::into_raw(Box::new(archive)),
+        Err(e) => fail(e.to_string(), ptr::null_mut()),
+    }
+}
+
+/// Message describing the last failure on the calling thread, or NULL if nothing failed yet.
+/// The string is valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn glb_last_error() -> *const c_char {
+    guard(ptr::null(), || {
+        LAST_ERROR.with(|last| match &*last.borrow() {
+            Some(message) => message.as_ptr(),
+            None => ptr::null(),
+        })
+    })
+}
+
+/// Reads archive from file and parses its FAT.
+/// Returns NULL if the file can't be read or FAT is broken.
+///
+/// # Safety
+///
+/// `path` has to be NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn glb_archive_open(path: *const c_char) -> *mut GlbArchive {
+    guard(ptr::null_mut(), || {
+        if path.is_null() {
+            return fail("path is NULL".to_owned(), ptr::null_mut());
+        }
+
+        let path = match CStr::from_ptr(path).to_str() {
+            Ok(path) => path,
+            Err(_) => return fail("path is not valid UTF-8".to_owned(), ptr::null_mut()),
+        };
+
+        match std::fs::read(path) {
+            Ok(bytes) => into_handle(GlbArchive::new(glb_rs::GlbArchive::new(bytes))),
+            Err(e) => fail(format!("{}: {}", path, e), ptr::null_mut()),
+        }
+    })
+}
+
+/// Copies archive from memory and parses its FAT.
+/// Returns NULL if FAT is broken.
+///
+/// # Safety
+///
+/// `data` has to point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn glb_archive_from_bytes(data: *const u8, len: usize) -> *mut GlbArchive {
+    guard(ptr::null_mut(), || {
+        if data.is_null() {
+            return fail("data is NULL".to_owned(), ptr::null_mut());
+        }
+
+        let bytes = std::slice::from_raw_parts(data, len).to_vec();
+        into_handle(GlbArchive::new(glb_rs::GlbArchive::new(bytes)))
+    })
+}
+
+/// Releases the archive. Names returned by `glb_archive_entry_name` are released with it.
+///
+/// # Safety
+///
+/// `archive` has to be NULL or returned by one of the open functions, and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn glb_archive_free(archive: *mut GlbArchive) {
+    guard((), || {
+        if !archive.is_null() {
+            drop(Box::from_raw(archive));
+        }
+    })
+}
+
+/// Number of files in FAT, header not included.
+///
+/// # Safety
+///
+/// `archive` has to be NULL or valid archive.
+#[no_mangle]
+pub unsafe extern "C" fn glb_archive_entry_count(archive: *const GlbArchive) -> usize {
+    guard(0, || {
+        match archive.as_ref() {
+            Some(archive) => archive.fat.entries.len(),
+            None => 0,
+        }
+    })
+}
+
+/// Filename of the entry at given position of FAT, which may be empty.
+/// The string is owned by the archive. Returns NULL if index is out of range.
+///
+/// # Safety
+///
+/// `archive` has to be NULL or valid archive.
+#[no_mangle]
+pub unsafe extern "C" fn glb_archive_entry_name(archive: *const GlbArchive, index: usize) -> *const c_char {
+    guard(ptr::null(), || {
+        let archive = match archive.as_ref() {
+            Some(archive) => archive,
+            None => return fail("archive is NULL".to_owned(), ptr::null()),
+        };
+
+        match archive.names.get(index) {
+            Some(name) => name.as_ptr(),
+            None => fail(format!("entry {} is out of range", index), ptr::null()),
+        }
+    })
+}
+
+/// Fills `info` with flag, offset and length of the entry at given position of FAT.
+/// Returns false if index is out of range.
+///
+/// # Safety
+///
+/// `archive` has to be NULL or valid archive, `info` has to be NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn glb_archive_entry_info(archive: *const GlbArchive, index: usize, info: *mut GlbEntryInfo) -> bool {
+    guard(false, || {
+        let (archive, info) = match (archive.as_ref(), info.as_mut()) {
+            (Some(archive), Some(info)) => (archive, info),
+            _ => return fail("archive or info is NULL".to_owned(), false),
+        };
+
+        let entry = match archive.fat.entries.get(index) {
+            Some(entry) => entry,
+            None => return fail(format!("entry {} is out of range", index), false),
+        };
+
+        let flag = match entry.flag {
+            Flag::Normal => GlbFlag::Normal,
+            Flag::Encrypted => GlbFlag::Encrypted,
+        };
+
+        *info = GlbEntryInfo { flag, offset: entry.offset, length: entry.length };
+        true
+    })
+}
+
+/// Finds position of the first entry with given filename and stores it into `index`.
+/// Returns false if there is no such entry.
+///
+/// # Safety
+///
+/// `archive` has to be NULL or valid archive, `filename` has to be NULL
+/// or NUL terminated string and `index` has to be NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn glb_archive_find_entry(archive: *const GlbArchive, filename: *const c_char, index: *mut usize) -> bool {
+    guard(false, || {
+        if filename.is_null() {
+            return fail("filename is NULL".to_owned(), false);
+        }
+
+        let (archive, index) = match (archive.as_ref(), index.as_mut()) {
+            (Some(archive), Some(index)) => (archive, index),
+            _ => return fail("archive or index is NULL".to_owned(), false),
+        };
+
+        let filename = CStr::from_ptr(filename);
+
+        match archive.names.iter().position(|name| name.as_c_str() == filename) {
+            Some(position) => {
+                *index = position;
+                true
+            }
+            None => fail(format!("{}: no such file in archive", filename.to_string_lossy()), false),
+        }
+    })
+}
+
+/// Reads the file at given position of FAT, decrypted if it's encrypted.
+///
+/// # Safety
+///
+/// `archive` has to be NULL or valid archive.
+#[no_mangle]
+pub unsafe extern "C" fn glb_archive_read_entry(archive: *const GlbArchive, index: usize) -> GlbBuffer {
+    guard(GlbBuffer::null(), || {
+        let archive = match archive.as_ref() {
+            Some(archive) => archive,
+            None => return fail("archive is NULL".to_owned(), GlbBuffer::null()),
+        };
+
+        match archive.read(index) {
+            Ok(file) => GlbBuffer::new(file.into_bytes()),
+            Err(e) => fail(e, GlbBuffer::null()),
+        }
+    })
+}
+
+/// Releases bytes returned by `glb_archive_read_entry` or `glb_archive_decode_pic`.
+///
+/// # Safety
+///
+/// `buffer` has to be returned by one of those functions and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn glb_buffer_free(buffer: GlbBuffer) {
+    guard((), || {
+        if !buffer.data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)));
+        }
+    })
+}
+
+/// Decodes the file at given position of FAT as VGA palette, such as PALETTE_DAT.
+/// Returns NULL if the file isn't palette.
+///
+/// # Safety
+///
+/// `archive` has to be NULL or valid archive.
+#[no_mangle]
+pub unsafe extern "C" fn glb_archive_read_palette(archive: *const GlbArchive, index: usize) -> *mut GlbPalette {
+    guard(ptr::null_mut(), || {
+        let archive = match archive.as_ref() {
+            Some(archive) => archive,
+            None => return fail("archive is NULL".to_owned(), ptr::null_mut()),
+        };
+
+        let palette = archive.read(index)
+            .and_then(|file| file.get_dat().map_err(|e| e.to_string()));
+
+        match palette {
+            Ok(palette) => Box::into_raw(Box::new(GlbPalette { palette })),
+            Err(e) => fail(e, ptr::null_mut()),
+        }
+    })
+}
+
+/// Releases palette returned by `glb_archive_read_palette`.
+///
+/// # Safety
+///
+/// `palette` has to be NULL or returned by `glb_archive_read_palette` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn glb_palette_free(palette: *mut GlbPalette) {
+    guard((), || {
+        if !palette.is_null() {
+            drop(Box::from_raw(palette));
+        }
+    })
+}
+
+/// Decodes the file at given position of FAT as PIC and converts it to RGBA
+/// with given palette. Pixels of returned image are NULL if the file isn't
+/// picture, its pixels don't fill its width and height, or it uses colors
+/// the palette doesn't have.
+///
+/// # Safety
+///
+/// `archive` and `palette` have to be NULL or valid.
+#[no_mangle]
+pub unsafe extern "C" fn glb_archive_decode_pic(archive: *const GlbArchive, index: usize, palette: *const GlbPalette) -> GlbImage {
+    guard(GlbImage::null(), || {
+        let (archive, palette) = match (archive.as_ref(), palette.as_ref()) {
+            (Some(archive), Some(palette)) => (archive, &palette.palette),
+            _ => return fail("archive or palette is NULL".to_owned(), GlbImage::null()),
+        };
+
+        let pic = match archive.read(index).and_then(|file| file.get_pic().map_err(|e| e.to_string())) {
+            Ok(pic) => pic,
+            Err(e) => return fail(e, GlbImage::null()),
+        };
+
+        let (width, height) = match (u32::try_from(pic.width), u32::try_from(pic.height)) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return fail(format!("{}: picture is too large", pic.filename), GlbImage::null()),
+        };
+
+        let rgba = match pic.try_get_argb(palette) {
+            Ok(argb) => argb.iter().flat_map(|p| [p.red, p.green, p.blue, p.alpha]).collect(),
+            Err(e) => return fail(e.to_string(), GlbImage::null()),
+        };
+
+        GlbImage { width, height, rgba: GlbBuffer::new(rgba) }
+    })
+}
@@ -0,0 +1,393 @@
+//! Python module `glb` wrapping glb-rs.
+//!
+//! Pixels, colors and tiles are returned as `Array`, which implements
+//! `__array_interface__`, so `numpy.asarray` turns it into numpy array without copying.
+
+use pyo3::exceptions::{PyIndexError, PyKeyError, PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyTuple};
+
+use glb_rs::{FatEntry, File, FileAllocationTable, Flag, GlbError, PicLayout, UntypedFile};
+
+fn value_error(e: GlbError) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// Read-only array of little endian values stored in `bytes`.
+#[pyclass(name = "Array", module = "glb", frozen)]
+struct PyArray {
+    data: Py<PyBytes>,
+    shape: Vec<usize>,
+    typestr: &'static str,
+}
+
+impl PyArray {
+    fn new(py: Python<'_>, data: &[u8], shape: Vec<usize>, typestr: &'static str) -> PyArray {
+        PyArray { data: PyBytes::new(py, data).unbind(), shape, typestr }
+    }
+}
+
+#[pymethods]
+impl PyArray {
+
+    #[getter]
+    fn shape<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyTuple>> {
+        PyTuple::new(py, &self.shape)
+    }
+
+    /// Type of items in numpy notation, `|u1` for bytes and `<u2` for tiles.
+    #[getter]
+    fn typestr(&self) -> &'static str {
+        self.typestr
+    }
+
+    #[getter]
+    fn __array_interface__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let interface = PyDict::new(py);
+        interface.set_item("version", 3)?;
+        interface.set_item("shape", self.shape(py)?)?;
+        interface.set_item("typestr", self.typestr)?;
+        interface.set_item("data", self.data.bind(py))?;
+        Ok(interface)
+    }
+
+    /// Items as bytes, row by row.
+    fn tobytes(&self, py: Python<'_>) -> Py<PyBytes> {
+        self.data.clone_ref(py)
+    }
+
+    fn __len__(&self) -> usize {
+        self.shape.first().copied().unwrap_or(0)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Array(shape={:?}, typestr='{}')", self.shape, self.typestr)
+    }
+}
+
+#[pyclass(name = "FatEntry", module = "glb", frozen, get_all)]
+struct PyFatEntry {
+    /// Position of the entry in FAT, header not included.
+    index: usize,
+    filename: String,
+
+    /// Either "normal" or "encrypted".
+    flag: &'static str,
+    offset: u32,
+    length: u32,
+}
+
+impl PyFatEntry {
+    fn new(index: usize, entry: &FatEntry) -> PyFatEntry {
+        let flag = match entry.flag {
+            Flag::Normal => "normal",
+            Flag::Encrypted => "encrypted",
+        };
+        PyFatEntry { index, filename: entry.filename.clone(), flag, offset: entry.offset, length: entry.length }
+    }
+}
+
+#[pymethods]
+impl PyFatEntry {
+    fn __repr__(&self) -> String {
+        format!("FatEntry(index={}, filename='{}', flag='{}', offset={}, length={})",
+            self.index, self.filename, self.flag, self.offset, self.length)
+    }
+}
+
+#[pyclass(name = "Text", module = "glb", frozen)]
+struct PyText {
+    text: glb_rs::Text,
+}
+
+#[pymethods]
+impl PyText {
+
+    #[getter]
+    fn filename(&self) -> &str {
+        &self.text.filename
+    }
+
+    #[getter]
+    fn text(&self) -> &str {
+        &self.text.text
+    }
+
+    fn __str__(&self) -> &str {
+        &self.text.text
+    }
+}
+
+#[pyclass(name = "Palette", module = "glb", frozen)]
+struct PyPalette {
+    palette: glb_rs::Palette,
+}
+
+#[pymethods]
+impl PyPalette {
+
+    #[getter]
+    fn filename(&self) -> &str {
+        &self.palette.filename
+    }
+
+    /// Colors scaled to 0-255, array of shape (colors, 4) with red, green, blue and alpha.
+    #[getter]
+    fn colors(&self, py: Python<'_>) -> PyArray {
+        let rgba: Vec<u8> = self.palette.palette.iter()
+            .flat_map(|p| [p.red, p.green, p.blue, p.alpha])
+            .collect();
+        PyArray::new(py, &rgba, vec![self.palette.palette.len(), 4], "|u1")
+    }
+
+    fn __len__(&self) -> usize {
+        self.palette.palette.len()
+    }
+}
+
+#[pyclass(name = "Pic", module = "glb", frozen)]
+struct PyPic {
+    pic: glb_rs::Pic,
+}
+
+#[pymethods]
+impl PyPic {
+
+    #[getter]
+    fn filename(&self) -> &str {
+        &self.pic.filename
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.pic.width
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        self.pic.height
+    }
+
+    /// Either "raw", "sprite" or "tile".
+    #[getter]
+    fn layout(&self) -> &'static str {
+        match self.pic.layout() {
+            PicLayout::Raw => "raw",
+            PicLayout::Sprite => "sprite",
+            PicLayout::Tile => "tile",
+        }
+    }
+
+    /// Header fields whose meaning is unknown, see `Pic::unknown1` to `Pic::unknown3`.
+    #[getter]
+    fn unknown(&self) -> (u32, u32, u32) {
+        (self.pic.unknown1(), self.pic.unknown2(), self.pic.unknown3())
+    }
+
+    /// Indexes into palette, array of shape (height, width). Transparent pixels are 0, see `mask`.
+    #[getter]
+    fn indices(&self, py: Python<'_>) -> PyResult<PyArray> {
+        let shape = self.shape()?;
+        let indices: Vec<u8> = self.pic.pixels.iter().map(|p| p.unwrap_or(0)).collect();
+        Ok(PyArray::new(py, &indices, shape, "|u1"))
+    }
+
+    /// 1 for opaque and 0 for transparent pixels, array of shape (height, width).
+    #[getter]
+    fn mask(&self, py: Python<'_>) -> PyResult<PyArray> {
+        let shape = self.shape()?;
+        let mask: Vec<u8> = self.pic.pixels.iter().map(|p| p.is_some() as u8).collect();
+        Ok(PyArray::new(py, &mask, shape, "|u1"))
+    }
+
+    /// Number of pixels, which differs from width times height
+    /// if the data of raw picture don't match its header.
+    fn __len__(&self) -> usize {
+        self.pic.pixels.len()
+    }
+
+    /// Colors of pixels, array of shape (height, width, 4) with red, green, blue and alpha.
+    /// Transparent pixels have alpha 0.
+    fn rgba(&self, py: Python<'_>, palette: &PyPalette) -> PyResult<PyArray> {
+        let rgba: Vec<u8> = self.pic.try_get_argb(&palette.palette).map_err(value_error)?.iter()
+            .flat_map(|p| [p.red, p.green, p.blue, p.alpha])
+            .collect();
+
+        let mut shape = self.shape()?;
+        shape.push(4);
+        Ok(PyArray::new(py, &rgba, shape, "|u1"))
+    }
+}
+
+impl PyPic {
+    fn shape(&self) -> PyResult<Vec<usize>> {
+        self.pic.check_size().map_err(value_error)?;
+        Ok(vec![self.pic.height, self.pic.width])
+    }
+}
+
+#[pyclass(name = "Map", module = "glb", frozen)]
+struct PyMap {
+    map: glb_rs::Map,
+}
+
+#[pymethods]
+impl PyMap {
+
+    #[getter]
+    fn filename(&self) -> &str {
+        &self.map.filename
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.map.width
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        self.map.height
+    }
+
+    #[getter]
+    fn actor_count(&self) -> u32 {
+        self.map.actor_count
+    }
+
+    #[getter]
+    fn file_size(&self) -> u32 {
+        self.map.file_size()
+    }
+
+    #[getter]
+    fn actor_offset(&self) -> u32 {
+        self.map.actor_offset()
+    }
+
+    /// Tile numbers, array of shape (height, width) of 16 bit integers.
+    #[getter]
+    fn tiles(&self, py: Python<'_>) -> PyArray {
+        let tiles: Vec<u8> = self.map.tiles.iter()
+            .flat_map(|row| row.iter())
+            .flat_map(|tile| tile.to_le_bytes())
+            .collect();
+        PyArray::new(py, &tiles, vec![self.map.height, self.map.width], "<u2")
+    }
+}
+
+// Decoded file as Python object, files of unknown type are returned as bytes.
+fn file_object(py: Python<'_>, file: File) -> PyResult<Py<PyAny>> {
+    let object = match file {
+        File::Text(text) => Py::new(py, PyText { text })?.into_any(),
+        File::Palette(palette) => Py::new(py, PyPalette { palette })?.into_any(),
+        File::Pic(pic) => Py::new(py, PyPic { pic })?.into_any(),
+        File::Map(map) => Py::new(py, PyMap { map })?.into_any(),
+        File::Tiles(tiles) => {
+            let pics = tiles.tiles.into_iter()
+                .map(|pic| Py::new(py, PyPic { pic }))
+                .collect::<PyResult<Vec<_>>>()?;
+            pics.into_pyobject(py)?.into_any().unbind()
+        }
+        File::Raw(raw) => PyBytes::new(py, &raw.bytes).into_any().unbind(),
+    };
+    Ok(object)
+}
+
+/// Entry is looked up either by its position in FAT, or by filename.
+#[derive(FromPyObject)]
+enum EntryKey {
+    Index(usize),
+    Filename(String),
+}
+
+#[pyclass(name = "GlbArchive", module = "glb", frozen)]
+struct PyGlbArchive {
+    archive: glb_rs::GlbArchive,
+    fat: FileAllocationTable,
+}
+
+impl PyGlbArchive {
+    fn new(archive: glb_rs::GlbArchive) -> PyResult<PyGlbArchive> {
+        let fat = archive.parse_fat().map_err(value_error)?;
+        Ok(PyGlbArchive { archive, fat })
+    }
+
+    fn find(&self, key: EntryKey) -> PyResult<&FatEntry> {
+        match key {
+            EntryKey::Index(index) => self.fat.entries.get(index)
+                .ok_or_else(|| PyIndexError::new_err(format!("entry {} is out of range", index))),
+            EntryKey::Filename(filename) => self.fat.get(&filename)
+                .ok_or_else(|| PyKeyError::new_err(filename)),
+        }
+    }
+
+    fn read_file(&self, key: EntryKey) -> PyResult<UntypedFile<'_>> {
+        UntypedFile::read_file(&self.archive, self.find(key)?).map_err(value_error)
+    }
+}
+
+#[pymethods]
+impl PyGlbArchive {
+
+    /// Reads archive from file and parses its FAT.
+    #[new]
+    fn open(path: &str) -> PyResult<PyGlbArchive> {
+        let bytes = std::fs::read(path).map_err(|e| PyOSError::new_err(format!("{}: {}", path, e)))?;
+        PyGlbArchive::new(glb_rs::GlbArchive::new(bytes))
+    }
+
+    /// Parses archive that is already in memory.
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<PyGlbArchive> {
+        PyGlbArchive::new(glb_rs::GlbArchive::new(data.to_vec()))
+    }
+
+    /// FAT entries in the order they are stored.
+    #[getter]
+    fn entries(&self) -> Vec<PyFatEntry> {
+        self.fat.entries.iter().enumerate()
+            .map(|(index, entry)| PyFatEntry::new(index, entry))
+            .collect()
+    }
+
+    fn __len__(&self) -> usize {
+        self.fat.entries.len()
+    }
+
+    fn __contains__(&self, filename: &str) -> bool {
+        self.fat.get(filename).is_some()
+    }
+
+    /// Decrypted bytes of the entry with given position or filename.
+    fn read<'py>(&self, py: Python<'py>, key: EntryKey) -> PyResult<Bound<'py, PyBytes>> {
+        Ok(PyBytes::new(py, self.read_file(key)?.bytes()))
+    }
+
+    /// Decodes the entry with given position or filename into Text, Palette, Pic or Map,
+    /// files of unknown type are returned as bytes.
+    fn decode(&self, py: Python<'_>, key: EntryKey) -> PyResult<Py<PyAny>> {
+        let file = self.read_file(key)?.decode();
+        file_object(py, file)
+    }
+
+    /// Decodes all files, returns list of pairs of FAT entry and decoded file.
+    /// Unlike `decode`, unnamed tiles between STARTG and ENDG are decoded as pictures.
+    fn files(&self, py: Python<'_>) -> PyResult<Vec<(PyFatEntry, Py<PyAny>)>> {
+        let extracted = self.archive.extract_files(&self.fat).map_err(value_error)?;
+        extracted.entries.into_iter()
+            .map(|e| Ok((PyFatEntry::new(e.index, &e.fat_entry), file_object(py, e.file)?)))
+            .collect()
+    }
+}
+
+#[pymodule]
+fn glb(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyGlbArchive>()?;
+    m.add_class::<PyFatEntry>()?;
+    m.add_class::<PyText>()?;
+    m.add_class::<PyPalette>()?;
+    m.add_class::<PyPic>()?;
+    m.add_class::<PyMap>()?;
+    m.add_class::<PyArray>()?;
+    Ok(())
+}
@@ -1,101 +1,272 @@
-use std::ops::{Index, Range, RangeFrom};
+use alloc::borrow::{Cow, ToOwned};
+use alloc::vec::Vec;
 
+use super::error::GlbError;
+
+/// Bytes borrowed from archive, which are copied into owned buffer
+/// only once they have to be decrypted, so the archive itself is never modified.
 #[derive(Debug, PartialEq)]
 pub struct Bytes<'a> {
-    bytes: &'a mut [u8],
+    bytes: Cow<'a, [u8]>,
 }
 
 impl<'a> Bytes<'a> {
-    pub fn from(bytes: &'a mut [u8]) -> Bytes<'a> {
-        Bytes { bytes }
+    pub fn from(bytes: &'a [u8]) -> Bytes<'a> {
+        Bytes { bytes: Cow::Borrowed(bytes) }
     }
 
-    pub fn len(&self) -> usize {
-        self.bytes.len()
+    pub fn from_vec(bytes: Vec<u8>) -> Bytes<'static> {
+        Bytes { bytes: Cow::Owned(bytes) }
     }
 
-    /// Reads unsigned 32bit little endian integer and iterates offset by 4.
-    pub fn read_u32(&self, offset: &mut usize) -> u32 {
-        let array = [
-            self.bytes[*offset],
-            self.bytes[*offset+1],
-            self.bytes[*offset+2],
-            self.bytes[*offset+3]
-        ];
-        *offset = *offset + 4;
-        u32::from_le_bytes(array)
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
     }
 
-    /// Reads unsigned 16bit little endian integer and iterates offset by 2.
-    pub fn read_u16(&self, offset: &mut usize) -> u16 {
-        let array: [u8; 2] = [self.bytes[*offset], self.bytes[*offset + 1]];
-        *offset = *offset + 2;
-        u16::from_le_bytes(array)
+    pub fn into_owned(self) -> Vec<u8> {
+        self.bytes.into_owned()
     }
 
-    pub fn as_text(&self) -> Option<&str> {
-        core::str::from_utf8(&self.bytes).ok()
+    pub fn into_cow(self) -> Cow<'a, [u8]> {
+        self.bytes
     }
 
+    pub fn as_text(&self) -> Result<&str, core::str::Utf8Error> {
+        core::str::from_utf8(&self.bytes)
+    }
 
+    /// Decrypts copy of the bytes, borrowed bytes are left untouched.
     pub fn decrypt(&mut self, encryption_key: &[u8]) {
+        decrypt(self.bytes.to_mut(), encryption_key);
+    }
+}
 
-        /*
-        1. Subtract the character value from the current position in the encryption key
-        2. Advance the position in the encryption key by one
-        3. If the end of the encryption key has been reached, go back to the first character
-        4. Subtract the value of the previous byte read
-        5. Logical AND with 0xFF to limit the result to 0-255
-        6. This byte is now decoded, move on to the next
-        */
-        
-        // The position in the encryption key does not start at 0. Instead, it starts at 25 modulo <length of key>.
-        let mut key_index: usize = 25 % encryption_key.len();
-
-        // The very first "previous byte read" value is the actual key character at the initial position.
-        let mut previous_byte_read = encryption_key[key_index];
-
-        for ix in 0..self.bytes.len() {
-
-            let mut decrypted_byte = self.bytes[ix] as i32;
-            decrypted_byte = decrypted_byte - (encryption_key[key_index] as i32);
-            decrypted_byte = decrypted_byte - previous_byte_read as i32;
-            decrypted_byte = decrypted_byte & 0xFF;
-            
-            previous_byte_read = self.bytes[ix];
-
-            self.bytes[ix] = decrypted_byte as u8;
-
-            key_index = key_index + 1;
-            if key_index == encryption_key.len() {
-                key_index = 0;
-            }
+/// Decrypts bytes in place with the Raptor cipher.
+/// Empty key leaves bytes unchanged.
+pub fn decrypt(bytes: &mut [u8], encryption_key: &[u8]) {
+
+    /*
+    1. Subtract the character value from the current position in the encryption key
+    2. Advance the position in the encryption key by one
+    3. If the end of the encryption key has been reached, go back to the first character
+    4. Subtract the value of the previous byte read
+    5. Logical AND with 0xFF to limit the result to 0-255
+    6. This byte is now decoded, move on to the next
+    */
+
+    if encryption_key.is_empty() {
+        return;
+    }
+
+    // The position in the encryption key does not start at 0. Instead, it starts at 25 modulo <length of key>.
+    let mut key_index: usize = 25 % encryption_key.len();
+
+    // The very first "previous byte read" value is the actual key character at the initial position.
+    let mut previous_byte_read = encryption_key[key_index];
+
+    for byte in bytes.iter_mut() {
+
+        let mut decrypted_byte = *byte as i32;
+        decrypted_byte -= encryption_key[key_index] as i32;
+        decrypted_byte -= previous_byte_read as i32;
+        decrypted_byte &= 0xFF;
+
+        previous_byte_read = *byte;
+
+        *byte = decrypted_byte as u8;
+
+        key_index += 1;
+        if key_index == encryption_key.len() {
+            key_index = 0;
         }
     }
 }
 
-impl<'a> Index<usize> for Bytes<'a> {
-    type Output = u8;
+/// Encrypts bytes in place with the Raptor cipher, inverse of `decrypt`.
+/// Empty key leaves bytes unchanged.
+pub fn encrypt(bytes: &mut [u8], encryption_key: &[u8]) {
 
-    fn index(&self, ix: usize) -> &Self::Output {
-        &self.bytes[ix]
+    if encryption_key.is_empty() {
+        return;
+    }
+
+    // Same walk through the key as in decrypt, except that the key
+    // and the previous encrypted byte are added instead of subtracted.
+    let mut key_index: usize = 25 % encryption_key.len();
+
+    let mut previous_byte_read = encryption_key[key_index];
+
+    for byte in bytes.iter_mut() {
+
+        let mut encrypted_byte = *byte as i32;
+        encrypted_byte += encryption_key[key_index] as i32;
+        encrypted_byte += previous_byte_read as i32;
+        encrypted_byte &= 0xFF;
+
+        previous_byte_read = encrypted_byte as u8;
+
+        *byte = encrypted_byte as u8;
+
+        key_index += 1;
+        if key_index == encryption_key.len() {
+            key_index = 0;
+        }
+    }
+}
+
+/// Decrypts bytes in place, starting the cipher over every `chunk_size` bytes.
+/// This is how FAT entries are stored, see `CHUNK_SIZE`.
+/// Chunk size of 0 decrypts all bytes as single chunk.
+pub fn decrypt_chunks(bytes: &mut [u8], encryption_key: &[u8], chunk_size: usize) {
+    if chunk_size == 0 {
+        return decrypt(bytes, encryption_key);
+    }
+    for chunk in bytes.chunks_mut(chunk_size) {
+        decrypt(chunk, encryption_key);
+    }
+}
+
+/// Encrypts bytes in place, starting the cipher over every `chunk_size` bytes,
+/// inverse of `decrypt_chunks`.
+pub fn encrypt_chunks(bytes: &mut [u8], encryption_key: &[u8], chunk_size: usize) {
+    if chunk_size == 0 {
+        return encrypt(bytes, encryption_key);
+    }
+    for chunk in bytes.chunks_mut(chunk_size) {
+        encrypt(chunk, encryption_key);
     }
 }
 
+/// Reads little endian values from the start of the bytes towards the end.
+/// Reading past the end returns `GlbError::UnexpectedEof` with position
+/// of the value and given filename, the cursor then stays where it was.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ByteCursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    filename: &'a str,
+}
 
-impl<'a> Index<Range<usize>> for Bytes<'a> {
-    type Output = [u8];
+impl<'a> ByteCursor<'a> {
 
-    fn index(&self, ix: Range<usize>) -> &Self::Output {
-        &self.bytes[ix]
+    pub fn new(bytes: &'a [u8], filename: &'a str) -> ByteCursor<'a> {
+        ByteCursor { bytes, position: 0, filename }
+    }
+
+    /// Number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Reads next `count` bytes.
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], GlbError> {
+        if count > self.remaining() {
+            return Err(GlbError::UnexpectedEof { filename: self.filename.to_owned(), offset: self.position });
+        }
+        let bytes = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    /// Reads all bytes that are left.
+    pub fn read_rest(&mut self) -> &'a [u8] {
+        let bytes = &self.bytes[self.position..];
+        self.position = self.bytes.len();
+        bytes
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, GlbError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, GlbError> {
+        let b = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, GlbError> {
+        let b = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ENCRYPTION_KEY;
+    use alloc::vec;
+
+    // Deterministic pseudo-random bytes, so that every byte value is covered.
+    fn sample(length: usize) -> Vec<u8> {
+        let mut state: u32 = 12345;
+        (0..length).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    fn keys() -> Vec<Vec<u8>> {
+        vec![vec![], vec![7], b"AB".to_vec(), sample(25), sample(26), ENCRYPTION_KEY.to_vec(), sample(300)]
+    }
+
+    #[test]
+    fn decrypt_inverts_encrypt() {
+        for key in keys() {
+            for length in [0, 1, 27, 28, 1000] {
+                let plain = sample(length);
+                let mut bytes = plain.clone();
+                encrypt(&mut bytes, &key);
+                if !key.is_empty() && length > 0 {
+                    assert_ne!(bytes, plain);
+                }
+                decrypt(&mut bytes, &key);
+                assert_eq!(bytes, plain, "key length {}", key.len());
+            }
+        }
+    }
+
+    #[test]
+    fn encrypt_inverts_decrypt() {
+        for key in keys() {
+            let encrypted = sample(1000);
+            let mut bytes = encrypted.clone();
+            decrypt(&mut bytes, &key);
+            encrypt(&mut bytes, &key);
+            assert_eq!(bytes, encrypted, "key length {}", key.len());
+        }
+    }
 
-impl<'a> Index<RangeFrom<usize>> for Bytes<'a> {
-    type Output = [u8];
+    #[test]
+    fn decrypt_chunks_inverts_encrypt_chunks() {
+        for key in keys() {
+            for chunk_size in [0, 1, 28, 100] {
+                let plain = sample(28 * 10 + 5);
+                let mut bytes = plain.clone();
+                encrypt_chunks(&mut bytes, &key, chunk_size);
+                decrypt_chunks(&mut bytes, &key, chunk_size);
+                assert_eq!(bytes, plain, "key length {}, chunk size {}", key.len(), chunk_size);
+            }
+        }
+    }
 
-    fn index(&self, ix: RangeFrom<usize>) -> &Self::Output {
-        &self.bytes[ix]
+    #[test]
+    fn chunks_start_cipher_over() {
+        let plain = sample(56);
+        let mut chunked = plain.clone();
+        encrypt_chunks(&mut chunked, ENCRYPTION_KEY, 28);
+        for (chunk, encrypted) in plain.chunks(28).zip(chunked.chunks(28)) {
+            let mut chunk = chunk.to_vec();
+            encrypt(&mut chunk, ENCRYPTION_KEY);
+            assert_eq!(chunk, encrypted);
+        }
     }
 }
@@ -0,0 +1,282 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use super::bytes::ByteCursor;
+
+/// Kinds of files that can be recognized by their content.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum FileKind {
+    Text,
+    Palette,
+    Pic,
+    Map,
+}
+
+/// Possible type of file with confidence between 0 and 1.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Guess {
+    pub kind: FileKind,
+    pub confidence: f32,
+}
+
+/// Confidence needed before file with unknown name is decoded according to the guess.
+pub const DETECT_THRESHOLD: f32 = 0.8;
+
+// Actors follow the tiles, each actor takes 24 bytes.
+// Same offset is found in every level of the original game.
+pub(crate) const MAP_ACTOR_OFFSET: u32 = 0x1524;
+pub(crate) const MAP_ACTOR_SIZE: u32 = 24;
+
+const PALETTE_SIZE: usize = 768;
+
+// Game pictures are much smaller, larger sprites are rejected before their pixels are allocated.
+pub(crate) const MAX_PIC_DIMENSION: usize = 4096;
+
+// Texts shorter than this get lower confidence, since few bytes are printable by chance.
+const MIN_TEXT_LENGTH: usize = 16;
+
+/// Guesses type of file from its content, most probable type first.
+/// Types that don't fit the content at all are left out.
+pub fn detect(bytes: &[u8]) -> Vec<Guess> {
+
+    let mut guesses: Vec<Guess> = [
+        (FileKind::Map, map_confidence(bytes)),
+        (FileKind::Pic, pic_confidence(bytes)),
+        (FileKind::Palette, palette_confidence(bytes)),
+        (FileKind::Text, text_confidence(bytes)),
+    ].iter()
+        .filter(|(_, confidence)| *confidence > 0.0)
+        .map(|(kind, confidence)| Guess { kind: *kind, confidence: *confidence })
+        .collect();
+
+    guesses.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap_or(Ordering::Equal));
+    guesses
+}
+
+/// Level starts with its own size and offset of actors, which is always 0x1524,
+/// actors then take the rest of the file in 24 byte records.
+fn map_confidence(bytes: &[u8]) -> f32 {
+
+    let mut cursor = ByteCursor::new(bytes, "");
+
+    let (file_size, actor_offset, actor_count) = match (cursor.read_u32(), cursor.read_u32(), cursor.read_u32()) {
+        (Ok(s), Ok(o), Ok(c)) => (s, o, c),
+        _ => return 0.0,
+    };
+
+    if actor_offset != MAP_ACTOR_OFFSET {
+        return 0.0;
+    }
+
+    let mut confidence = 0.6;
+
+    if file_size as usize == bytes.len() {
+        confidence += 0.25;
+    }
+
+    if file_size >= actor_offset && (file_size - actor_offset) / MAP_ACTOR_SIZE == actor_count {
+        confidence += 0.1;
+    }
+
+    confidence
+}
+
+/// Raw picture has to have exactly width * height bytes of data,
+/// sprite has to consist of blocks that fit into the image and end with terminator.
+fn pic_confidence(bytes: &[u8]) -> f32 {
+
+    let mut cursor = ByteCursor::new(bytes, "");
+
+    let (unknown_1, unknown_2, i_line_count, width, height) = match (
+        cursor.read_u32(), cursor.read_u32(), cursor.read_u32(), cursor.read_u32(), cursor.read_u32()
+    ) {
+        (Ok(u1), Ok(u2), Ok(l), Ok(w), Ok(h)) => (u1, u2, l, w as usize, h as usize),
+        _ => return 0.0,
+    };
+
+    if width == 0 || height == 0 || width > MAX_PIC_DIMENSION || height > MAX_PIC_DIMENSION {
+        return 0.0;
+    }
+
+    if i_line_count == 0 {
+
+        if cursor.remaining() != width * height {
+            return 0.0;
+        }
+
+        return if unknown_1 == 1 && unknown_2 == 1 { 0.95 } else { 0.85 };
+    }
+
+    loop {
+        let (i_pos_x, i_pos_y, i_linear_offset, i_count) = match (
+            cursor.read_u32(), cursor.read_u32(), cursor.read_u32(), cursor.read_u32()
+        ) {
+            (Ok(x), Ok(y), Ok(o), Ok(c)) => (x as usize, y as usize, o, c as usize),
+            _ => return 0.0,
+        };
+
+        if i_linear_offset == 0xFFFFFFFF && i_count == 0xFFFFFFFF {
+            break;
+        }
+
+        if i_pos_x >= width || i_pos_y >= height || ((i_pos_y * width) + i_pos_x).saturating_add(i_count) > width * height {
+            return 0.0;
+        }
+
+        if cursor.read_bytes(i_count).is_err() {
+            return 0.0;
+        }
+    }
+
+    if cursor.is_empty() { 0.9 } else { 0.5 }
+}
+
+/// VGA palette has 3 bytes per color and only 6 bits per channel,
+/// full palette of 256 colors has 768 bytes. Any other data of that size
+/// with bytes up to 63, such as quiet 6bit sound, can't be told from palette
+/// and is taken for one.
+fn palette_confidence(bytes: &[u8]) -> f32 {
+
+    if bytes.is_empty() || !bytes.len().is_multiple_of(3) || bytes.iter().any(|b| *b > 63) {
+        return 0.0;
+    }
+
+    if bytes.len().is_multiple_of(PALETTE_SIZE) { 0.9 } else { 0.4 }
+}
+
+/// Share of printable ASCII characters, for valid UTF-8 only.
+fn text_confidence(bytes: &[u8]) -> f32 {
+
+    if bytes.is_empty() || core::str::from_utf8(bytes).is_err() {
+        return 0.0;
+    }
+
+    let printable = bytes.iter()
+        .filter(|b| (0x20..0x7F).contains(*b) || **b == b'\r' || **b == b'\n' || **b == b'\t')
+        .count();
+
+    let confidence = 0.9 * printable as f32 / bytes.len() as f32;
+
+    if bytes.len() < MIN_TEXT_LENGTH {
+        confidence * bytes.len() as f32 / MIN_TEXT_LENGTH as f32
+    } else {
+        confidence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn fields(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    // The best guess, which is the one `UntypedFile::decode` uses if it's above the threshold.
+    fn best(bytes: &[u8]) -> Option<Guess> {
+        detect(bytes).into_iter().next()
+    }
+
+    // Confidences are sums of floats, so they are compared with tolerance.
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-6, "{} is not {}", actual, expected);
+    }
+
+    fn assert_detected(bytes: &[u8], kind: FileKind, confidence: f32) {
+        let guess = best(bytes).unwrap();
+        assert_eq!(guess.kind, kind);
+        assert_close(guess.confidence, confidence);
+        assert!(confidence >= DETECT_THRESHOLD);
+    }
+
+    #[test]
+    fn raw_pic() {
+        let mut bytes = fields(&[1, 1, 0, 4, 3]);
+        bytes.extend(100..112);
+        assert_detected(&bytes, FileKind::Pic, 0.95);
+
+        // Unusual header fields lower the confidence.
+        bytes[0] = 0;
+        assert_detected(&bytes, FileKind::Pic, 0.85);
+
+        // Data have to match width and height.
+        bytes.push(0);
+        assert_eq!(pic_confidence(&bytes), 0.0);
+    }
+
+    #[test]
+    fn sprite_pic() {
+        let mut bytes = fields(&[0, 0, 2, 4, 3]);
+        bytes.extend(fields(&[1, 0, 1, 2]));
+        bytes.extend([100, 101]);
+        bytes.extend(fields(&[0, 2, 8, 4]));
+        bytes.extend([102, 103, 104, 105]);
+        bytes.extend(fields(&[0xFFFFFFFF; 4]));
+        assert_detected(&bytes, FileKind::Pic, 0.9);
+
+        // Trailing bytes after terminator make it doubtful.
+        bytes.push(0);
+        assert_eq!(pic_confidence(&bytes), 0.5);
+        assert!(best(&bytes).unwrap().confidence < DETECT_THRESHOLD);
+    }
+
+    #[test]
+    fn sprite_block_outside_of_image() {
+        let mut bytes = fields(&[0, 0, 1, 4, 3]);
+        bytes.extend(fields(&[3, 2, 11, 2]));
+        bytes.extend([100, 101]);
+        bytes.extend(fields(&[0xFFFFFFFF; 4]));
+        assert_eq!(pic_confidence(&bytes), 0.0);
+    }
+
+    #[test]
+    fn map() {
+        let mut bytes = vec![0u8; MAP_ACTOR_OFFSET as usize + 2 * MAP_ACTOR_SIZE as usize];
+        let file_size = bytes.len() as u32;
+        bytes[..12].copy_from_slice(&fields(&[file_size, MAP_ACTOR_OFFSET, 2]));
+        assert_detected(&bytes, FileKind::Map, 0.95);
+
+        // Wrong actor count.
+        bytes[8] = 3;
+        assert_close(map_confidence(&bytes), 0.85);
+
+        // Map is recognized by its actor offset only.
+        bytes[4] = 0;
+        assert_eq!(map_confidence(&bytes), 0.0);
+    }
+
+    #[test]
+    fn palette() {
+        let bytes: Vec<u8> = (0..768).map(|ix| (ix * 7 % 64) as u8).collect();
+        assert_detected(&bytes, FileKind::Palette, 0.9);
+
+        assert_eq!(palette_confidence(&bytes[..48]), 0.4);
+        assert_eq!(palette_confidence(&bytes[..47]), 0.0);
+        assert_eq!(palette_confidence(&[64; 768]), 0.0);
+    }
+
+    #[test]
+    fn text() {
+        let bytes = b"Welcome to Bravo Sector, pilot.\r\nWatch for ground targets.\r\n";
+        assert_detected(bytes, FileKind::Text, 0.9);
+
+        // Short texts aren't trusted.
+        assert!(text_confidence(b"OK") < DETECT_THRESHOLD);
+        assert_eq!(text_confidence(&[0xFF, 0xFE]), 0.0);
+    }
+
+    #[test]
+    fn quiet_sound_is_taken_for_palette() {
+        // 768 bytes of 6bit samples can't be told from palette, see `palette_confidence`.
+        let bytes: Vec<u8> = (0..768i32).map(|ix| 32 + (ix % 32 - 16).unsigned_abs() as u8).collect();
+        assert_detected(&bytes, FileKind::Palette, 0.9);
+    }
+
+    #[test]
+    fn binary_data() {
+        let bytes: Vec<u8> = (0..1000u32).map(|ix| (ix.wrapping_mul(2654435761) >> 24) as u8).collect();
+        assert!(detect(&bytes).iter().all(|g| g.confidence < DETECT_THRESHOLD));
+        assert!(detect(&[]).is_empty());
+    }
+}
@@ -0,0 +1,559 @@
+use std::borrow::ToOwned;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::format;
+use std::string::{String, ToString};
+use std::vec;
+use std::vec::Vec;
+
+use super::error::GlbError;
+use super::extracted::{Extracted, ExtractedEntry};
+use super::file::*;
+use super::glb_archive::*;
+
+/// Difference of single entry between two archives.
+/// Indexes are positions of entries in FAT of the old and the new archive.
+#[derive(Debug, PartialEq, Clone)]
+pub enum EntryChange {
+    Added { filename: String, new_index: usize },
+
+    Removed { filename: String, old_index: usize },
+
+    /// Entry is the same, but its position relative to other entries changed.
+    Moved { filename: String, old_index: usize, new_index: usize },
+
+    /// Content or flag of the entry changed.
+    Changed {
+        filename: String,
+        old_index: usize,
+        new_index: usize,
+        flag: Option<(Flag, Flag)>,
+        content: Option<ContentChange>,
+    },
+}
+
+/// What changed inside of the file, according to its type.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ContentChange {
+    Text { lines: Vec<LineChange> },
+
+    /// Indexes of colors that differ, or are present only in one of palettes.
+    Palette { indices: Vec<usize> },
+
+    /// Bounding boxes of areas of changed pixels. Whole image is one region if its size changed.
+    Pic { old_size: (usize, usize), new_size: (usize, usize), regions: Vec<Region> },
+
+    Map { cells: Vec<CellChange>, actor_count: Option<(u32, u32)> },
+
+    /// Files of unknown type, files whose type changed, and files that differ
+    /// only in undecoded parts, such as unknown header fields of pictures.
+    Bytes { old_length: u32, new_length: u32 },
+}
+
+/// Line numbers start at 1.
+#[derive(Debug, PartialEq, Clone)]
+pub enum LineChange {
+    Removed { old_line: usize, text: String },
+    Added { new_line: usize, text: String },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CellChange {
+    pub x: usize,
+    pub y: usize,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// Result of `diff`, removed entries in old order followed by the rest in new order.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ArchiveDiff {
+    pub changes: Vec<EntryChange>,
+}
+
+impl ArchiveDiff {
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Compares two archives. Entries are paired by filename, entries with the same name,
+/// such as unnamed tiles, are paired in the order they appear in FAT.
+pub fn diff<A: AsRef<[u8]>, B: AsRef<[u8]>>(old: &GlbArchive<A>, new: &GlbArchive<B>) -> Result<ArchiveDiff, GlbError> {
+
+    let old = old.extract_files(&old.parse_fat()?)?;
+    let new = new.extract_files(&new.parse_fat()?)?;
+
+    let old_keys = keys(&old);
+    let new_keys = keys(&new);
+
+    let old_by_key: HashMap<&(String, usize), usize> = old_keys.iter().enumerate().map(|(ix, k)| (k, ix)).collect();
+    let new_by_key: HashMap<&(String, usize), usize> = new_keys.iter().enumerate().map(|(ix, k)| (k, ix)).collect();
+
+    // Entries present in both archives that keep their relative order.
+    let common_old: Vec<&(String, usize)> = old_keys.iter().filter(|k| new_by_key.contains_key(k)).collect();
+    let common_new: Vec<&(String, usize)> = new_keys.iter().filter(|k| old_by_key.contains_key(k)).collect();
+    let in_order: HashSet<&(String, usize)> = longest_common_subsequence(&common_old, &common_new)
+        .into_iter()
+        .map(|(ix, _)| common_old[ix])
+        .collect();
+
+    let mut changes = Vec::new();
+
+    for (old_index, key) in old_keys.iter().enumerate() {
+        if !new_by_key.contains_key(key) {
+            changes.push(EntryChange::Removed { filename: key.0.clone(), old_index });
+        }
+    }
+
+    for (new_index, key) in new_keys.iter().enumerate() {
+        let filename = key.0.clone();
+
+        let old_index = match old_by_key.get(key) {
+            Some(ix) => *ix,
+            None => {
+                changes.push(EntryChange::Added { filename, new_index });
+                continue;
+            }
+        };
+
+        let old_entry = &old.entries[old_index];
+        let new_entry = &new.entries[new_index];
+
+        let flag = if old_entry.fat_entry.flag != new_entry.fat_entry.flag {
+            Some((old_entry.fat_entry.flag, new_entry.fat_entry.flag))
+        } else {
+            None
+        };
+
+        // Flag of raw files is already compared above.
+        let same_content = match (&old_entry.file, &new_entry.file) {
+            (File::Raw(old_raw), File::Raw(new_raw)) => old_raw.bytes == new_raw.bytes,
+            (old_file, new_file) => old_file == new_file,
+        };
+
+        let content = if !same_content {
+            Some(content_change(old_entry, new_entry))
+        } else {
+            None
+        };
+
+        if flag.is_some() || content.is_some() {
+            changes.push(EntryChange::Changed { filename, old_index, new_index, flag, content });
+        } else if !in_order.contains(key) {
+            changes.push(EntryChange::Moved { filename, old_index, new_index });
+        }
+    }
+
+    Ok(ArchiveDiff { changes })
+}
+
+// Filename and number of preceding entries with the same name.
+fn keys(extracted: &Extracted) -> Vec<(String, usize)> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    extracted.entries.iter().map(|e| {
+        let filename = e.fat_entry.filename.as_str();
+        let occurrence = seen.entry(filename).or_insert(0);
+        *occurrence += 1;
+        (filename.to_owned(), *occurrence - 1)
+    }).collect()
+}
+
+// Pairs of indexes of items that form longest common subsequence.
+fn longest_common_subsequence<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+
+    // lengths[i][j] is length of LCS of a[i..] and b[j..].
+    let mut lengths = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+// Files whose decoded content is the same, such as pictures that differ
+// only in unknown header fields, are reported as changed bytes.
+fn content_change(old: &ExtractedEntry, new: &ExtractedEntry) -> ContentChange {
+    let bytes = ContentChange::Bytes { old_length: old.fat_entry.length, new_length: new.fat_entry.length };
+
+    match (&old.file, &new.file) {
+        (File::Text(old), File::Text(new)) if old.text != new.text =>
+            ContentChange::Text { lines: text_changes(&old.text, &new.text) },
+        (File::Palette(old), File::Palette(new)) if old.palette != new.palette =>
+            ContentChange::Palette { indices: palette_changes(old, new) },
+        (File::Pic(old), File::Pic(new)) if old.check_size().is_ok() && new.check_size().is_ok() => {
+            let regions = pic_changes(old, new);
+            if regions.is_empty() {
+                return bytes;
+            }
+            ContentChange::Pic { old_size: (old.width, old.height), new_size: (new.width, new.height), regions }
+        }
+        (File::Map(old), File::Map(new)) => {
+            let cells = map_changes(old, new);
+            let actor_count = if old.actor_count != new.actor_count { Some((old.actor_count, new.actor_count)) } else { None };
+            if cells.is_empty() && actor_count.is_none() {
+                return bytes;
+            }
+            ContentChange::Map { cells, actor_count }
+        }
+        _ => bytes,
+    }
+}
+
+fn text_changes(old: &str, new: &str) -> Vec<LineChange> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    // Sentinel pair flushes lines after the last common one.
+    let mut pairs = longest_common_subsequence(&old, &new);
+    pairs.push((old.len(), new.len()));
+
+    for (common_i, common_j) in pairs {
+        for (ix, text) in old.iter().enumerate().take(common_i).skip(i) {
+            changes.push(LineChange::Removed { old_line: ix + 1, text: text.to_string() });
+        }
+        for (ix, text) in new.iter().enumerate().take(common_j).skip(j) {
+            changes.push(LineChange::Added { new_line: ix + 1, text: text.to_string() });
+        }
+        i = common_i + 1;
+        j = common_j + 1;
+    }
+    changes
+}
+
+fn palette_changes(old: &Palette, new: &Palette) -> Vec<usize> {
+    let length = old.palette.len().max(new.palette.len());
+    (0..length)
+        .filter(|ix| old.palette.get(*ix) != new.palette.get(*ix))
+        .collect()
+}
+
+// Bounding boxes of groups of changed pixels that touch each other,
+// both pictures have to have width * height pixels.
+fn pic_changes(old: &Pic, new: &Pic) -> Vec<Region> {
+
+    if old.width != new.width || old.height != new.height {
+        return vec![Region { x: 0, y: 0, width: new.width, height: new.height }];
+    }
+
+    let (width, height) = (new.width, new.height);
+    let mut changed: Vec<bool> = old.pixels.iter().zip(new.pixels.iter())
+        .map(|(old, new)| old != new)
+        .collect();
+
+    let mut regions = Vec::new();
+
+    for start in 0..changed.len() {
+        if !changed[start] {
+            continue;
+        }
+
+        let mut region = Region { x: start % width, y: start / width, width: 1, height: 1 };
+        let (mut right, mut bottom) = (region.x, region.y);
+
+        changed[start] = false;
+        let mut stack = vec![start];
+
+        while let Some(ix) = stack.pop() {
+            let (x, y) = (ix % width, ix / width);
+            region.x = region.x.min(x);
+            region.y = region.y.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+
+            let mut neighbours = Vec::with_capacity(4);
+            if x > 0 { neighbours.push(ix - 1); }
+            if x + 1 < width { neighbours.push(ix + 1); }
+            if y > 0 { neighbours.push(ix - width); }
+            if y + 1 < height { neighbours.push(ix + width); }
+
+            for neighbour in neighbours {
+                if changed[neighbour] {
+                    changed[neighbour] = false;
+                    stack.push(neighbour);
+                }
+            }
+        }
+
+        region.width = right - region.x + 1;
+        region.height = bottom - region.y + 1;
+        regions.push(region);
+    }
+
+    regions
+}
+
+fn map_changes(old: &Map, new: &Map) -> Vec<CellChange> {
+    let mut cells = Vec::new();
+    for (y, (old_row, new_row)) in old.tiles.iter().zip(new.tiles.iter()).enumerate() {
+        for (x, (old, new)) in old_row.iter().zip(new_row.iter()).enumerate() {
+            if old != new {
+                cells.push(CellChange { x, y, old: *old, new: *new });
+            }
+        }
+    }
+    cells
+}
+
+impl fmt::Display for EntryChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryChange::Added { filename, new_index } =>
+                write!(f, "+ {:?} at {}", filename, new_index),
+            EntryChange::Removed { filename, old_index } =>
+                write!(f, "- {:?} at {}", filename, old_index),
+            EntryChange::Moved { filename, old_index, new_index } =>
+                write!(f, "> {:?} moved from {} to {}", filename, old_index, new_index),
+            EntryChange::Changed { filename, old_index, new_index, flag, content } => {
+                write!(f, "~ {:?} at {}", filename, old_index)?;
+                if old_index != new_index {
+                    write!(f, " -> {}", new_index)?;
+                }
+                if let Some((old, new)) = flag {
+                    write!(f, "\n    flag {:?} -> {:?}", old, new)?;
+                }
+                if let Some(content) = content {
+                    write!(f, "\n{}", content)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for ContentChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentChange::Text { lines } => {
+                for (ix, line) in lines.iter().enumerate() {
+                    if ix > 0 {
+                        writeln!(f)?;
+                    }
+                    match line {
+                        LineChange::Removed { old_line, text } => write!(f, "    {:>5} - {}", old_line, text)?,
+                        LineChange::Added { new_line, text } => write!(f, "    {:>5} + {}", new_line, text)?,
+                    }
+                }
+                Ok(())
+            }
+            ContentChange::Palette { indices } =>
+                write!(f, "    palette indices {:?} changed", indices),
+            ContentChange::Pic { old_size, new_size, regions } => {
+                if old_size != new_size {
+                    write!(f, "    size {}x{} -> {}x{}", old_size.0, old_size.1, new_size.0, new_size.1)
+                } else {
+                    let regions: Vec<String> = regions.iter()
+                        .map(|r| format!("{}x{} at {},{}", r.width, r.height, r.x, r.y))
+                        .collect();
+                    write!(f, "    pixels changed in {}", regions.join(", "))
+                }
+            }
+            ContentChange::Map { cells, actor_count } => {
+                write!(f, "    {} tiles changed", cells.len())?;
+                for cell in cells {
+                    write!(f, "\n    tile {},{}: {} -> {}", cell.x, cell.y, cell.old, cell.new)?;
+                }
+                if let Some((old, new)) = actor_count {
+                    write!(f, "\n    actor count {} -> {}", old, new)?;
+                }
+                Ok(())
+            }
+            ContentChange::Bytes { old_length, new_length } =>
+                write!(f, "    content changed, {} -> {} bytes", old_length, new_length),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::{MAP_ACTOR_OFFSET, MAP_ACTOR_SIZE};
+    use crate::glb_writer::GlbArchiveBuilder;
+
+    fn archive(files: &[(&str, Vec<u8>)]) -> GlbArchive {
+        let mut builder = GlbArchiveBuilder::new();
+        for (filename, bytes) in files {
+            builder.add(filename, Flag::Normal, bytes.clone()).unwrap();
+        }
+        builder.build().unwrap()
+    }
+
+    fn changes(old: &[(&str, Vec<u8>)], new: &[(&str, Vec<u8>)]) -> Vec<EntryChange> {
+        diff(&archive(old), &archive(new)).unwrap().changes
+    }
+
+    // Content of single changed entry.
+    fn content(filename: &str, old: Vec<u8>, new: Vec<u8>) -> ContentChange {
+        match changes(&[(filename, old)], &[(filename, new)]).as_slice() {
+            [EntryChange::Changed { flag: None, content: Some(content), .. }] => content.clone(),
+            changes => panic!("{:?}", changes),
+        }
+    }
+
+    fn fields(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn pic(pixels: &[u8]) -> Vec<u8> {
+        Pic::new("", 4, 3, pixels.iter().map(|p| Some(*p)).collect()).encode()
+    }
+
+    fn map(tile: u16) -> Vec<u8> {
+        let file_size = MAP_ACTOR_OFFSET + MAP_ACTOR_SIZE;
+        let mut bytes = vec![0u8; file_size as usize];
+        bytes[..12].copy_from_slice(&fields(&[file_size, MAP_ACTOR_OFFSET, 1]));
+        bytes[16..18].copy_from_slice(&tile.to_le_bytes());
+        bytes
+    }
+
+    fn text(text: &str) -> Vec<u8> {
+        text.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn same_archives() {
+        let files = [("A_TXT", text("a")), ("", vec![1, 2])];
+        assert!(diff(&archive(&files), &archive(&files)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn added_removed_and_moved() {
+        let old = [("A_TXT", text("a")), ("B_TXT", text("b")), ("C_TXT", text("c")), ("E_TXT", text("e")), ("F_TXT", text("f"))];
+        let new = [("E_TXT", text("e")), ("A_TXT", text("a")), ("C_TXT", text("c")), ("F_TXT", text("f")), ("D_TXT", text("d"))];
+
+        assert_eq!(changes(&old, &new), [
+            EntryChange::Removed { filename: "B_TXT".to_owned(), old_index: 1 },
+            EntryChange::Moved { filename: "E_TXT".to_owned(), old_index: 3, new_index: 0 },
+            EntryChange::Added { filename: "D_TXT".to_owned(), new_index: 4 },
+        ]);
+    }
+
+    #[test]
+    fn unnamed_entries_are_paired_in_order() {
+        let old = [("", vec![1]), ("", vec![2])];
+        let new = [("", vec![1]), ("", vec![3]), ("", vec![4])];
+
+        assert_eq!(changes(&old, &new), [
+            EntryChange::Changed {
+                filename: String::new(),
+                old_index: 1,
+                new_index: 1,
+                flag: None,
+                content: Some(ContentChange::Bytes { old_length: 1, new_length: 1 }),
+            },
+            EntryChange::Added { filename: String::new(), new_index: 2 },
+        ]);
+    }
+
+    #[test]
+    fn text_lines() {
+        let change = content("A_TXT", text("one\ntwo\nthree\n"), text("one\n2\nthree\nfour\n"));
+        assert_eq!(change, ContentChange::Text { lines: vec![
+            LineChange::Removed { old_line: 2, text: "two".to_owned() },
+            LineChange::Added { new_line: 2, text: "2".to_owned() },
+            LineChange::Added { new_line: 4, text: "four".to_owned() },
+        ] });
+    }
+
+    #[test]
+    fn palette_indices() {
+        let old = vec![0u8; 12];
+        let mut new = old.clone();
+        new[4] = 10;
+        new.extend([1, 2, 3]);
+
+        assert_eq!(content("PALETTE_DAT", old, new), ContentChange::Palette { indices: vec![1, 4] });
+    }
+
+    #[test]
+    fn pic_regions() {
+        let old = [0; 12];
+        let mut new = old;
+        new[0] = 1;
+        new[1] = 1;
+        new[11] = 1;
+
+        assert_eq!(content("A_PIC", pic(&old), pic(&new)), ContentChange::Pic {
+            old_size: (4, 3),
+            new_size: (4, 3),
+            regions: vec![
+                Region { x: 0, y: 0, width: 2, height: 1 },
+                Region { x: 3, y: 2, width: 1, height: 1 },
+            ],
+        });
+    }
+
+    #[test]
+    fn pic_header_only() {
+        let old = pic(&[0; 12]);
+        let mut new = old.clone();
+        new[0] = 2;
+
+        assert_eq!(content("A_PIC", old, new), ContentChange::Bytes { old_length: 32, new_length: 32 });
+    }
+
+    #[test]
+    fn pic_smaller_than_header() {
+        let mut old = fields(&[1, 1, 0, 0xFFFFFFFF, 0xFFFFFFFF]);
+        let mut new = old.clone();
+        old.push(1);
+        new.push(2);
+
+        assert_eq!(content("A_PIC", old, new), ContentChange::Bytes { old_length: 21, new_length: 21 });
+    }
+
+    #[test]
+    fn map_cells() {
+        assert_eq!(content("A_MAP", map(0), map(7)), ContentChange::Map {
+            cells: vec![CellChange { x: 1, y: 0, old: 0, new: 7 }],
+            actor_count: None,
+        });
+    }
+
+    #[test]
+    fn flag_only() {
+        let mut old = GlbArchiveBuilder::new();
+        old.add("A_TXT", Flag::Normal, text("a")).unwrap();
+        let mut new = GlbArchiveBuilder::new();
+        new.add("A_TXT", Flag::Encrypted, text("a")).unwrap();
+
+        assert_eq!(diff(&old.build().unwrap(), &new.build().unwrap()).unwrap().changes, [EntryChange::Changed {
+            filename: "A_TXT".to_owned(),
+            old_index: 0,
+            new_index: 0,
+            flag: Some((Flag::Normal, Flag::Encrypted)),
+            content: None,
+        }]);
+    }
+}
@@ -0,0 +1,111 @@
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::string::ToString;
+use core::fmt;
+
+/// Error returned when archive or one of its files can't be parsed.
+///
+/// Offsets of FAT errors are relative to the start of the archive,
+/// offsets of file errors are relative to the start of the file.
+#[derive(Debug, PartialEq, Clone)]
+pub enum GlbError {
+    /// Data ended before the value at given offset could be read.
+    UnexpectedEof { filename: String, offset: usize },
+
+    /// FAT entries have to be decrypted by chunks of 28 bytes.
+    InvalidChunkSize { offset: usize, size: usize },
+
+    /// FAT entry flag is neither 0 (normal) nor 1 (encrypted).
+    UnknownFlag { filename: String, offset: usize, flag: u32 },
+
+    /// FAT entry filename is not valid UTF-8.
+    InvalidFilename { offset: usize },
+
+    /// FAT entry points outside of the archive.
+    EntryOutOfBounds { filename: String, offset: usize, length: usize },
+
+    /// Text file is not valid UTF-8.
+    InvalidText { filename: String, offset: usize },
+
+    /// Sprite block writes pixel outside of the image.
+    PixelOutOfBounds { filename: String, offset: usize },
+
+    /// Filename can't be stored in 16 byte FAT field,
+    /// it has to be at most 15 ASCII characters without NUL.
+    UnsupportedFilename { filename: String },
+
+    /// Archive would be larger than 4 GiB, which FAT offsets can't address.
+    ArchiveTooLarge { filename: String },
+
+    /// There is no file with given filename.
+    EntryNotFound { filename: String },
+
+    /// Reading or seeking the underlying reader failed.
+    #[cfg(feature = "std")]
+    Io { kind: std::io::ErrorKind, message: String },
+
+    /// None of the candidate keys decrypts the archive into sane FAT.
+    UnknownKey,
+
+    /// Image can't be decoded.
+    InvalidImage { filename: String, message: String },
+
+    /// All colors of the palette are reserved, so there is none to convert image to.
+    EmptyPalette { filename: String },
+
+    /// Picture uses color that the palette doesn't have.
+    ColorOutOfPalette { filename: String, color: u8 },
+
+    /// Raw picture has another number of pixels than its width and height say.
+    PixelCountMismatch { filename: String, pixels: usize, width: usize, height: usize },
+}
+
+impl fmt::Display for GlbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GlbError::UnexpectedEof { filename, offset } =>
+                write!(f, "{}: unexpected end of data at offset {}", filename, offset),
+            GlbError::InvalidChunkSize { offset, size } =>
+                write!(f, "FAT entry at offset {} has {} bytes instead of 28", offset, size),
+            GlbError::UnknownFlag { filename, offset, flag } =>
+                write!(f, "{}: unknown flag {} at offset {}", filename, flag, offset),
+            GlbError::InvalidFilename { offset } =>
+                write!(f, "FAT entry at offset {} has filename that is not valid UTF-8", offset),
+            GlbError::EntryOutOfBounds { filename, offset, length } =>
+                write!(f, "{}: {} bytes at offset {} lie outside of the archive", filename, length, offset),
+            GlbError::InvalidText { filename, offset } =>
+                write!(f, "{}: invalid UTF-8 at offset {}", filename, offset),
+            GlbError::PixelOutOfBounds { filename, offset } =>
+                write!(f, "{}: sprite block at offset {} writes outside of the image", filename, offset),
+            GlbError::UnsupportedFilename { filename } =>
+                write!(f, "{}: filename has to be at most 15 ASCII characters", filename),
+            GlbError::ArchiveTooLarge { filename } =>
+                write!(f, "{}: archive can't be larger than 4 GiB", filename),
+            GlbError::EntryNotFound { filename } =>
+                write!(f, "{}: no such file in archive", filename),
+            #[cfg(feature = "std")]
+            GlbError::Io { message, .. } =>
+                write!(f, "I/O error: {}", message),
+            GlbError::UnknownKey =>
+                write!(f, "archive can't be decrypted with any of the candidate keys"),
+            GlbError::InvalidImage { filename, message } =>
+                write!(f, "{}: invalid image: {}", filename, message),
+            GlbError::EmptyPalette { filename } =>
+                write!(f, "{}: palette has no color that isn't reserved", filename),
+            GlbError::ColorOutOfPalette { filename, color } =>
+                write!(f, "{}: color {} is not in the palette", filename, color),
+            GlbError::PixelCountMismatch { filename, pixels, width, height } =>
+                write!(f, "{}: picture has {} pixels, header says {}x{}", filename, pixels, width, height),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for GlbError {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for GlbError {
+    fn from(e: std::io::Error) -> GlbError {
+        GlbError::Io { kind: e.kind(), message: e.to_string() }
+    }
+}
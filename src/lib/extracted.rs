@@ -1,12 +1,84 @@
-use super::{File, Tiles};
+use super::{FatEntry, File, Tiles};
 use std::collections::HashMap;
+use std::string::String;
+use std::vec::Vec;
 
+/// Single FAT entry and the file decoded from it.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExtractedEntry {
+
+    /// Position of the entry in FAT, header not included.
+    pub index: usize,
+
+    pub fat_entry: FatEntry,
+
+    /// `File::Raw` if the type of the file wasn't recognized.
+    pub file: File,
+}
+
+/// All entries of archive in FAT order, with lookup by filename.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "ExtractedFields"))]
 pub struct Extracted {
-    
-    pub named_files: HashMap<String, File>,
 
+    /// Every FAT entry in the order it's stored in archive,
+    /// that is `entries[i].index == i`.
+    pub entries: Vec<ExtractedEntry>,
+
+    /// Tiles between STARTG and ENDG markers, in FAT order.
+    /// They are also present among the entries.
     pub tiles: Tiles,
+
+    /// Indexes into entries by filename, there may be several entries with the same name.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    names: HashMap<String, Vec<usize>>,
+}
+
+// Name index is not serialized, it's built again from entries.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct ExtractedFields {
+    entries: Vec<ExtractedEntry>,
+    tiles: Tiles,
+}
+
+#[cfg(feature = "serde")]
+impl From<ExtractedFields> for Extracted {
+    fn from(fields: ExtractedFields) -> Extracted {
+        Extracted::new(fields.entries, fields.tiles)
+    }
+}
+
+impl Extracted {
+
+    pub fn new(entries: Vec<ExtractedEntry>, tiles: Tiles) -> Extracted {
+        let mut names: HashMap<String, Vec<usize>> = HashMap::with_capacity(entries.len());
+        for (ix, entry) in entries.iter().enumerate() {
+            names.entry(entry.fat_entry.filename.clone()).or_default().push(ix);
+        }
+        Extracted { entries, tiles, names }
+    }
+
+    /// Returns file decoded from the first entry with given filename.
+    pub fn get(&self, filename: &str) -> Option<&File> {
+        self.get_all(filename).next().map(|e| &e.file)
+    }
+
+    /// Returns all entries with given filename in FAT order.
+    pub fn get_all(&self, filename: &str) -> impl Iterator<Item = &ExtractedEntry> + '_ {
+        self.names.get(filename)
+            .map(|ixs| ixs.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(move |ix| &self.entries[*ix])
+    }
+
+    /// Iterates over all files in FAT order.
+    pub fn files(&self) -> impl Iterator<Item = &File> + '_ {
+        self.entries.iter().map(|e| &e.file)
+    }
 }
 
 /*
@@ -1,18 +1,31 @@
+use alloc::borrow::{Cow, ToOwned};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[cfg(feature = "image")]
 use image::{ImageBuffer, RgbaImage, Rgba};
 
 use super::glb_archive::*;
-use super::bytes::Bytes;
+use super::bytes::{ByteCursor, Bytes};
+use super::detect::*;
+use super::error::GlbError;
 
 const MAP_WIDTH: usize = 9;
 const MAP_HEIGHT: usize = 150;
 
+// Five 32bit fields in front of PIC data.
+const PIC_HEADER_SIZE: usize = 20;
+
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Text {
     pub filename: String,
     pub text: String,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ArgbPixel {
     pub alpha: u8,
     pub red: u8,
@@ -21,13 +34,31 @@ pub struct ArgbPixel {
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Palette {
     pub filename: String,
     pub palette:  Vec<ArgbPixel>
 }
 
+/// How the picture is stored in the archive.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PicLayout {
+    /// One byte per pixel, without transparency.
+    Raw,
+
+    /// Runs of opaque pixels, see `UntypedFile::get_pic`.
+    Sprite,
+
+    /// Raw pixels read by `UntypedFile::get_tile`, which ignores the line count.
+    /// `GlbArchive::extract_files` decodes tiles with `UntypedFile::get_pic`,
+    /// so tiles it returns are `PicLayout::Raw`.
+    Tile,
+}
+
 /// https://moddingwiki.shikadi.net/wiki/Raptor_PIC_Format
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pic {
     pub filename: String,
     pub width:  usize,
@@ -36,9 +67,66 @@ pub struct Pic {
     /// Indexes into palette. Palette has to be provided to get RGB values.
     /// Option::None indicates transparent pixel.
     pub pixels: Vec<Option<u8>>,
+
+    layout: PicLayout,
+    unknown1: u32,
+    unknown2: u32,
+    unknown3: u32,
 }
 
 impl Pic {
+
+    /// Picture that is stored raw if all its pixels are opaque, or as sprite otherwise.
+    /// Unknown header fields get the values found in game files, 1 and 1 for raw pictures
+    /// and 0 and 0 for sprites.
+    pub fn new(filename: &str, width: usize, height: usize, pixels: Vec<Option<u8>>) -> Pic {
+        let mut pic = Pic {
+            filename: filename.to_owned(),
+            width,
+            height,
+            pixels,
+            layout: PicLayout::Raw,
+            unknown1: 1,
+            unknown2: 1,
+            unknown3: 0,
+        };
+
+        if !pic.pixels.iter().all(Option::is_some) {
+            pic.layout = PicLayout::Sprite;
+            pic.unknown1 = 0;
+            pic.unknown2 = 0;
+            pic.unknown3 = pic.runs().len().max(1) as u32;
+        }
+
+        pic
+    }
+
+    pub fn layout(&self) -> PicLayout {
+        self.layout
+    }
+
+    /// First field of the header. Raw pictures have it always 1,
+    /// meaning of the value in sprites is unknown.
+    pub fn unknown1(&self) -> u32 {
+        self.unknown1
+    }
+
+    /// Second field of the header. Raw pictures have it always 1,
+    /// meaning of the value in sprites is unknown.
+    pub fn unknown2(&self) -> u32 {
+        self.unknown2
+    }
+
+    /// Third field of the header as it was read. It's the line count of pictures,
+    /// which is 0 for raw pictures and number of sprite blocks otherwise.
+    /// Tiles are raw pictures with 0 as well, except those read by
+    /// `UntypedFile::get_tile`, which keeps the value without checking it.
+    pub fn unknown3(&self) -> u32 {
+        self.unknown3
+    }
+
+    /// Colors of pixels, transparent pixels are transparent black.
+    /// Panics if a pixel uses color the palette doesn't have, see `Pic::try_get_argb`.
     pub fn get_argb(&self, palette: &Palette) -> Vec<ArgbPixel> {
         let mut argb_pixels = Vec::with_capacity(self.pixels.len());
         for pix in &self.pixels {
@@ -53,6 +141,33 @@ impl Pic {
         argb_pixels
     }
 
+    /// Same as `Pic::get_argb`, except that it fails if the picture doesn't have
+    /// width * height pixels or a pixel uses color the palette doesn't have.
+    pub fn try_get_argb(&self, palette: &Palette) -> Result<Vec<ArgbPixel>, GlbError> {
+        self.check_size()?;
+
+        if let Some(color) = self.pixels.iter().flatten().find(|ix| **ix as usize >= palette.palette.len()) {
+            return Err(GlbError::ColorOutOfPalette { filename: self.filename.clone(), color: *color });
+        }
+
+        Ok(self.get_argb(palette))
+    }
+
+    /// Checks that the picture has width * height pixels. Raw pictures take
+    /// their pixels from the data, which may not match the header.
+    pub fn check_size(&self) -> Result<(), GlbError> {
+        if self.width.checked_mul(self.height) != Some(self.pixels.len()) {
+            return Err(GlbError::PixelCountMismatch {
+                filename: self.filename.clone(),
+                pixels: self.pixels.len(),
+                width: self.width,
+                height: self.height,
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "image")]
     pub fn to_imagebuffer(&self, palette: &Palette) -> RgbaImage {
         let width = self.width as u32;
         let height = self.height as u32;
@@ -75,10 +190,96 @@ impl Pic {
 
         img
     }
+
+    /// Encodes the picture in Raptor PIC format, inverse of `UntypedFile::get_pic`
+    /// and `UntypedFile::get_tile`. Raw pictures and tiles are stored as raw VGA data,
+    /// transparent pixels of tiles become 0. Sprites, and raw pictures that got
+    /// transparent pixels, are stored as sprite blocks, one block for each run
+    /// of opaque pixels in a row. Unknown header fields are kept, except that line
+    /// count of sprites is counted again. Width and height are stored as 32bit integers.
+    pub fn encode(&self) -> Vec<u8> {
+
+        let mut bytes = Vec::with_capacity(PIC_HEADER_SIZE + self.pixels.len());
+
+        let push_u32 = |bytes: &mut Vec<u8>, value: u32| bytes.extend_from_slice(&value.to_le_bytes());
+
+        let raw_header = match self.layout {
+            PicLayout::Tile => Some(self.unknown3),
+            PicLayout::Raw if self.pixels.iter().all(Option::is_some) => Some(0),
+            _ => None,
+        };
+
+        if let Some(unknown3) = raw_header {
+            for value in [self.unknown1, self.unknown2, unknown3, self.width as u32, self.height as u32] {
+                push_u32(&mut bytes, value);
+            }
+            bytes.extend(self.pixels.iter().map(|p| p.unwrap_or(0)));
+            return bytes;
+        }
+
+        let runs = self.runs();
+
+        // Picture without any opaque pixel still needs one block,
+        // otherwise the line count of 0 would mark it as raw.
+        let line_count = runs.len().max(1);
+
+        for value in [self.unknown1, self.unknown2, line_count as u32, self.width as u32, self.height as u32] {
+            push_u32(&mut bytes, value);
+        }
+
+        if runs.is_empty() {
+            for value in [0, 0, 0, 0] {
+                push_u32(&mut bytes, value);
+            }
+        }
+
+        for (x, y, start, end) in runs {
+            for value in [x, y, start, end - start] {
+                push_u32(&mut bytes, value as u32);
+            }
+            bytes.extend(self.pixels[start..end].iter().flatten());
+        }
+
+        for _ in 0..4 {
+            push_u32(&mut bytes, 0xFFFFFFFF);
+        }
+
+        bytes
+    }
+
+    // Maximal runs of opaque pixels within rows, as position of the first pixel
+    // and range of the run in pixels.
+    fn runs(&self) -> Vec<(usize, usize, usize, usize)> {
+
+        let mut runs = Vec::new();
+
+        if self.width == 0 {
+            return runs;
+        }
+
+        for (y, row) in self.pixels.chunks(self.width).enumerate() {
+            let mut x = 0;
+            while x < row.len() {
+                if row[x].is_none() {
+                    x += 1;
+                    continue;
+                }
+                let run_start = x;
+                while x < row.len() && row[x].is_some() {
+                    x += 1;
+                }
+                let start = y * self.width + run_start;
+                runs.push((run_start, y, start, start + x - run_start));
+            }
+        }
+
+        runs
+    }
 }
 
 /// https://moddingwiki.shikadi.net/wiki/Raptor_Level_Format
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Map {
     pub filename: String,
 
@@ -88,21 +289,86 @@ pub struct Map {
     pub actor_count: u32,
 
     /// Index into tileset
+    #[cfg_attr(feature = "serde", serde(with = "map_tiles"))]
     pub tiles: [[u16; MAP_WIDTH]; MAP_HEIGHT],
+
+    file_size: u32,
+    actor_offset: u32,
+}
+
+impl Map {
+
+    /// Map with header fields computed the way the game stores them.
+    pub fn new(filename: &str, actor_count: u32, tiles: [[u16; MAP_WIDTH]; MAP_HEIGHT]) -> Map {
+        Map {
+            filename: filename.to_owned(),
+            width: MAP_WIDTH,
+            height: MAP_HEIGHT,
+            actor_count,
+            tiles,
+            file_size: MAP_ACTOR_OFFSET.wrapping_add(actor_count.wrapping_mul(MAP_ACTOR_SIZE)),
+            actor_offset: MAP_ACTOR_OFFSET,
+        }
+    }
+
+    /// Size of the whole level file as stored in its header,
+    /// which is `actor_offset + 24 * actor_count` in game files.
+    pub fn file_size(&self) -> u32 {
+        self.file_size
+    }
+
+    /// Position of actors in the level file as stored in its header, always 0x1524 in game files.
+    pub fn actor_offset(&self) -> u32 {
+        self.actor_offset
+    }
 }
 
+/// Serde implements arrays only up to 32 items, so tiles are serialized as sequence of rows.
+#[cfg(feature = "serde")]
+mod map_tiles {
+    use super::{MAP_HEIGHT, MAP_WIDTH};
+    use alloc::vec::Vec;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use core::convert::TryInto;
+
+    pub fn serialize<S: Serializer>(tiles: &[[u16; MAP_WIDTH]; MAP_HEIGHT], serializer: S) -> Result<S::Ok, S::Error> {
+        tiles[..].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[[u16; MAP_WIDTH]; MAP_HEIGHT], D::Error> {
+        let rows: Vec<[u16; MAP_WIDTH]> = Vec::deserialize(deserializer)?;
+        let length = rows.len();
+        rows.try_into().map_err(|_| D::Error::invalid_length(length, &"150 rows of tiles"))
+    }
+}
+
+/// File whose type wasn't recognized, such as sounds, music and fonts,
+/// or STARTG and ENDG markers. Bytes are already decrypted.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Raw {
+    pub filename: String,
+    pub flag: Flag,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Tiles {
     pub tiles: Vec<Pic>,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum File {
     Text(Text),
     Palette(Palette),
     Pic(Pic),
     Map(Map),
     Tiles(Tiles),
+    Raw(Raw),
 }
 
 
@@ -111,55 +377,155 @@ pub enum File {
 pub struct UntypedFile<'a> {
     bytes: Bytes<'a>,
     pub filename: String,
+    pub flag: Flag,
 }
 
 impl<'a> UntypedFile<'a> {
 
-    /// Copy bytes representing single file from slice into new vector.
-    /// If the file is encrypted, this function performs decryption.
-    pub fn read_file(archive: &'a mut GlbArchive, entry: &FatEntry) -> UntypedFile<'a> {
+    /// Borrows bytes representing single file from archive.
+    /// If the file is encrypted, it's decrypted into new buffer,
+    /// archive bytes are left untouched.
+    pub fn read_file<B: AsRef<[u8]>>(archive: &'a GlbArchive<B>, entry: &FatEntry) -> Result<UntypedFile<'a>, GlbError> {
+        UntypedFile::read_slice(archive.bytes.as_ref(), entry, &archive.key)
+    }
 
-        let filename = entry.filename.clone();
+    pub(crate) fn read_slice(archive_bytes: &'a [u8], entry: &FatEntry, key: &[u8]) -> Result<UntypedFile<'a>, GlbError> {
 
         let length = entry.length as usize;
         let start_offset = entry.offset as usize;
-        let end_offset = start_offset + length;
+        let end_offset = start_offset.checked_add(length);
 
-        let bytes = &mut archive.bytes[start_offset..end_offset];
-        let mut bytes = Bytes::from(bytes);
+        let bytes = match end_offset {
+            Some(end_offset) if end_offset <= archive_bytes.len() => &archive_bytes[start_offset..end_offset],
+            _ => return Err(GlbError::EntryOutOfBounds { filename: entry.filename.clone(), offset: start_offset, length }),
+        };
+
+        Ok(UntypedFile::new(entry, Bytes::from(bytes), key))
+    }
+
+    /// Takes ownership of bytes of single file, which were read by other means
+    /// than from `GlbArchive`, and decrypts them with given key if necessary.
+    pub fn from_vec(entry: &FatEntry, bytes: Vec<u8>, key: &[u8]) -> UntypedFile<'static> {
+        UntypedFile::new(entry, Bytes::from_vec(bytes), key)
+    }
+
+    fn new(entry: &FatEntry, mut bytes: Bytes<'a>, key: &[u8]) -> UntypedFile<'a> {
 
         if entry.flag == Flag::Encrypted {
-            bytes.decrypt(ENCRYPTION_KEY);
+            bytes.decrypt(key);
         }
 
-        UntypedFile { bytes, filename }
+        UntypedFile { bytes, filename: entry.filename.clone(), flag: entry.flag }
+    }
+
+    /// Decrypted content of the file.
+    pub fn bytes(&self) -> &[u8] {
+        self.bytes.as_slice()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes.into_owned()
     }
 
-    pub fn get_txt(&self) -> Option<Text> {
+    /// Content of the file, which is still borrowed from archive if it didn't need decryption.
+    pub fn into_cow(self) -> Cow<'a, [u8]> {
+        self.bytes.into_cow()
+    }
+
+    fn cursor(&self) -> ByteCursor<'_> {
+        ByteCursor::new(self.bytes.as_slice(), &self.filename)
+    }
+
+    /// Decodes file according to its filename suffix. If the type can't be told
+    /// from the filename, it's detected from the content. Returns `File::Raw`
+    /// if neither works, which is also the case of STARTG and ENDG markers,
+    /// and if the file can't be decoded as its filename says, so callers
+    /// can decode such files themselves. See `UntypedFile::decode_strict`
+    /// for the reason why such file failed.
+    pub fn decode(&self) -> File {
+        self.decode_strict().unwrap_or_else(|_| File::Raw(self.get_raw()))
+    }
+
+    /// Same as `UntypedFile::decode`, except that it returns the error
+    /// if the file can't be decoded as its filename says.
+    pub fn decode_strict(&self) -> Result<File, GlbError> {
+
+        let filename = &self.filename;
+
+        if filename.ends_with("TXT")
+        {
+            Ok(File::Text(self.get_txt()?))
+        }
+        else if filename.ends_with("_DAT")
+        {
+            Ok(File::Palette(self.get_dat()?))
+        }
+        else if filename.ends_with("_PIC")   ||
+                filename.ends_with("_PIC//") ||
+                filename.ends_with("_BLK")
+        {
+            Ok(File::Pic(self.get_pic()?))
+        }
+        else if filename.ends_with("_MAP")
+        {
+            Ok(File::Map(self.get_map()?))
+        }
+        else
+        {
+            Ok(self.decode_detected().unwrap_or_else(|| File::Raw(self.get_raw())))
+        }
+    }
+
+    /// Guesses type of the file from its content, see `detect`.
+    pub fn detect(&self) -> Vec<Guess> {
+        detect(self.bytes.as_slice())
+    }
+
+    // Decodes file as the best guess, if it's good enough and the file can be decoded that way.
+    fn decode_detected(&self) -> Option<File> {
+        let guess = self.detect().into_iter().next().filter(|g| g.confidence >= DETECT_THRESHOLD)?;
+        match guess.kind {
+            FileKind::Text => self.get_txt().ok().map(File::Text),
+            FileKind::Palette => self.get_dat().ok().map(File::Palette),
+            FileKind::Pic => self.get_pic().ok().map(File::Pic),
+            FileKind::Map => self.get_map().ok().map(File::Map),
+        }
+    }
+
+    pub fn get_raw(&self) -> Raw {
+        Raw { filename: self.filename.clone(), flag: self.flag, bytes: self.bytes.as_slice().to_vec() }
+    }
+
+    pub fn get_txt(&self) -> Result<Text, GlbError> {
         self.bytes.as_text()
             .map(|s| Text { filename: self.filename.clone(), text: s.to_owned() })
+            .map_err(|e| GlbError::InvalidText { filename: self.filename.clone(), offset: e.valid_up_to() })
     }
 
     /// Parses VGA pallete.
     /// https://moddingwiki.shikadi.net/wiki/VGA_Palette
-    pub fn get_dat(&self) -> Option<Palette> {
-
-        let mut palette: Vec<ArgbPixel> = Vec::with_capacity(self.bytes.len() / 2);
-
-        for ix in (0..self.bytes.len()).step_by(3) {
-            let red = ((self.bytes[ix] as u32 * 255) / 63) as u8;
-            let green = ((self.bytes[ix+1] as u32 * 255) / 63) as u8;
-            let blue = ((self.bytes[ix+2] as u32 * 255) / 63) as u8;
+    pub fn get_dat(&self) -> Result<Palette, GlbError> {
+
+        let mut cursor = self.cursor();
+        let mut palette: Vec<ArgbPixel> = Vec::with_capacity(cursor.remaining() / 3);
+
+        while !cursor.is_empty() {
+            let red = cursor.read_u8()?;
+            let green = cursor.read_u8()?;
+            let blue = cursor.read_u8()?;
+            let red = ((red as u32 * 255) / 63) as u8;
+            let green = ((green as u32 * 255) / 63) as u8;
+            let blue = ((blue as u32 * 255) / 63) as u8;
             palette.push(ArgbPixel { alpha: 255, red, green, blue });
         }
 
-        Some(Palette { filename: self.filename.clone(), palette })
+        Ok(Palette { filename: self.filename.clone(), palette })
     }
 
     /// Parses Raptor PIC format.
     /// https://moddingwiki.shikadi.net/wiki/Raptor_PIC_Format
     /// https://moddingwiki.shikadi.net/wiki/Raw_VGA_Image
-    pub fn get_pic(&self) -> Option<Pic> {
+    pub fn get_pic(&self) -> Result<Pic, GlbError> {
 
         let filename = self.filename.clone();
         
@@ -172,17 +538,17 @@ impl<'a> UntypedFile<'a> {
         UINT8 	    data[] 	    8bpp raw VGA data, one byte per pixel; or sprite layout blocks 
         */
 
-        let mut offset: usize = 0;
+        let mut cursor = self.cursor();
 
-        let _unknown_1 = self.bytes.read_u32(&mut offset);
-        let _unknown_2 = self.bytes.read_u32(&mut offset);
-        let i_line_count = self.bytes.read_u32(&mut offset);
-        let width = self.bytes.read_u32(&mut offset) as usize;
-        let height = self.bytes.read_u32(&mut offset) as usize;
+        let unknown1 = cursor.read_u32()?;
+        let unknown2 = cursor.read_u32()?;
+        let i_line_count = cursor.read_u32()?;
+        let width = cursor.read_u32()? as usize;
+        let height = cursor.read_u32()? as usize;
         
         if i_line_count == 0 {
-            let pixels: Vec<Option<u8>> = self.bytes[offset..].into_iter().map(|b| Some(*b)).collect();
-            return Some(Pic { filename: self.filename.clone(), width, height, pixels });
+            let pixels: Vec<Option<u8>> = cursor.read_rest().iter().map(|b| Some(*b)).collect();
+            return Ok(Pic { filename, width, height, pixels, layout: PicLayout::Raw, unknown1, unknown2, unknown3: i_line_count });
         }
 
         /*
@@ -193,35 +559,43 @@ impl<'a> UntypedFile<'a> {
         16 | BYTE[iCount] | bPixels		    pixels to write
         */
 
-        let mut pixels: Vec<Option<u8>> = vec![None; width*height];
+        if width > MAX_PIC_DIMENSION || height > MAX_PIC_DIMENSION {
+            return Err(GlbError::PixelOutOfBounds { filename: self.filename.clone(), offset: cursor.position() });
+        }
+
+        let pixels_count = width * height;
+
+        let mut pixels: Vec<Option<u8>> = vec![None; pixels_count];
 
         loop {
 
-            let i_pos_x = self.bytes.read_u32(&mut offset) as usize;
-            let i_pos_y = self.bytes.read_u32(&mut offset) as usize;
-            let i_linear_offset = self.bytes.read_u32(&mut offset);
-            let i_count = self.bytes.read_u32(&mut offset) as usize;
+            let block_offset = cursor.position();
+
+            let i_pos_x = cursor.read_u32()? as usize;
+            let i_pos_y = cursor.read_u32()? as usize;
+            let i_linear_offset = cursor.read_u32()?;
+            let i_count = cursor.read_u32()? as usize;
 
             if i_linear_offset == 0xFFFFFFFF && i_count == 0xFFFFFFFF {
                 break;
             }
 
-            let block_end = offset+i_count;
-            let mut pass = 0;
-            while offset < block_end {
-                let palette_ix = self.bytes[offset];
-                let pixels_ix = (i_pos_y*width) + i_pos_x + pass;
-                pass = pass + 1;
-                pixels[pixels_ix] = Some(palette_ix);
-                offset = offset + 1;
+            let block = i_pos_y.checked_mul(width)
+                .and_then(|start| start.checked_add(i_pos_x))
+                .and_then(|start| Some(start..start.checked_add(i_count)?))
+                .and_then(|range| pixels.get_mut(range))
+                .ok_or(GlbError::PixelOutOfBounds { filename: self.filename.clone(), offset: block_offset })?;
+
+            for (pixel, palette_ix) in block.iter_mut().zip(cursor.read_bytes(i_count)?) {
+                *pixel = Some(*palette_ix);
             }
         }
 
-        return Some(Pic { filename, width, height, pixels });
+        Ok(Pic { filename, width, height, pixels, layout: PicLayout::Sprite, unknown1, unknown2, unknown3: i_line_count })
     }
 
     
-    pub fn get_map(&self) -> Option<Map> {
+    pub fn get_map(&self) -> Result<Map, GlbError> {
 
         /*
         0 | UINT32LE       | iFileSize    | size of the entire level file
@@ -232,20 +606,20 @@ impl<'a> UntypedFile<'a> {
         
         let filename = self.filename.clone();
 
-        let mut offset: usize = 0;
+        let mut cursor = self.cursor();
         
-        let _file_size = self.bytes.read_u32(&mut offset) as usize;
-        let _actor_offset = self.bytes.read_u32(&mut offset);
-        let actor_count = self.bytes.read_u32(&mut offset);
-        let _tile_data = self.bytes.read_u32(&mut offset);
+        let file_size = cursor.read_u32()?;
+        let actor_offset = cursor.read_u32()?;
+        let actor_count = cursor.read_u32()?;
+        let _tile_data = cursor.read_u32()?;
 
         let mut tiles: [[u16; MAP_WIDTH]; MAP_HEIGHT] = [[0; MAP_WIDTH]; MAP_HEIGHT];
 
         for y in 0..MAP_HEIGHT {
             for x in 0..MAP_WIDTH {
 
-                let tile_number = self.bytes.read_u16(&mut offset);
-                let _tileset_number = self.bytes.read_u16(&mut offset);
+                let tile_number = cursor.read_u16()?;
+                let _tileset_number = cursor.read_u16()?;
 
                 let mut x = x + 1;
                 let mut y = y;
@@ -254,7 +628,7 @@ impl<'a> UntypedFile<'a> {
                     x = 0;
 
                     if y < MAP_HEIGHT-1 {
-                        y = y + 1;
+                        y += 1;
                     }
                 }
 
@@ -268,13 +642,15 @@ impl<'a> UntypedFile<'a> {
             filename,
             actor_count,
             tiles,
+            file_size,
+            actor_offset,
         };
 
-        Some(map)
+        Ok(map)
     }
     
     
-    pub fn get_tile(&self) -> Option<Pic> {
+    pub fn get_tile(&self) -> Result<Pic, GlbError> {
         /*
         UINT32LE 	unknown1 	? always 1?
         UINT32LE 	unknown2 	? always 0?
@@ -286,20 +662,119 @@ impl<'a> UntypedFile<'a> {
 
         let filename = self.filename.clone();
 
-        let mut offset: usize = 0;
-        let _unknown_1 = self.bytes.read_u32(&mut offset);
-        let _unknown_2 = self.bytes.read_u32(&mut offset);
-        let _unknown_3 = self.bytes.read_u32(&mut offset);
-        let width = self.bytes.read_u32(&mut offset) as usize;
-        let height = self.bytes.read_u32(&mut offset) as usize;
-        let data  = self.bytes[offset..].to_vec();
+        let mut cursor = self.cursor();
+        let unknown1 = cursor.read_u32()?;
+        let unknown2 = cursor.read_u32()?;
+        let unknown3 = cursor.read_u32()?;
+        let width = cursor.read_u32()? as usize;
+        let height = cursor.read_u32()? as usize;
+        let data  = cursor.read_rest().to_vec();
 
-        let mut pixels: Vec<Option<u8>> = Vec::with_capacity(data.len() / 2);
+        let mut pixels: Vec<Option<u8>> = Vec::with_capacity(data.len());
         for palette_ix in data {
             pixels.push(Some(palette_ix))
         }
 
-        return Some(Pic { filename, width, height, pixels });
+        Ok(Pic { filename, width, height, pixels, layout: PicLayout::Tile, unknown1, unknown2, unknown3 })
     }
     
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glb_archive::{FatEntry, Flag};
+
+    fn untyped(filename: &str, bytes: Vec<u8>) -> UntypedFile<'static> {
+        let entry = FatEntry { filename: filename.to_owned(), flag: Flag::Normal, offset: 0, length: 0 };
+        UntypedFile::from_vec(&entry, bytes, &[])
+    }
+
+    fn fields(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn round_trip(pic: &Pic) -> Pic {
+        untyped(&pic.filename, pic.encode()).get_pic().unwrap()
+    }
+
+    #[test]
+    fn raw_pic_round_trips() {
+        let pixels = (0..12).map(Some).collect();
+        let pic = Pic::new("RAW_PIC", 4, 3, pixels);
+
+        assert_eq!(pic.layout(), PicLayout::Raw);
+        assert_eq!(pic.encode().len(), PIC_HEADER_SIZE + 12);
+        assert_eq!(round_trip(&pic), pic);
+    }
+
+    #[test]
+    fn sparse_sprite_round_trips() {
+        let pixels = vec![
+            None, Some(1), Some(2), None, Some(3),
+            None, None, None, None, None,
+            Some(4), Some(5), Some(6), Some(7), Some(8),
+            Some(9), None, Some(0), None, None,
+        ];
+        let pic = Pic::new("SPARSE_PIC", 5, 4, pixels);
+
+        assert_eq!(pic.layout(), PicLayout::Sprite);
+        assert_eq!(pic.unknown3(), 5);
+        assert_eq!(round_trip(&pic), pic);
+    }
+
+    #[test]
+    fn transparent_sprite_round_trips() {
+        let pic = Pic::new("EMPTY_PIC", 3, 2, vec![None; 6]);
+
+        assert_eq!(pic.layout(), PicLayout::Sprite);
+        assert_eq!(pic.unknown3(), 1);
+        assert_eq!(round_trip(&pic), pic);
+    }
+
+    #[test]
+    fn decoded_pic_encodes_to_same_bytes() {
+        let mut raw = fields(&[1, 1, 0, 2, 2]);
+        raw.extend([1, 2, 3, 4]);
+        assert_eq!(untyped("RAW_PIC", raw.clone()).get_pic().unwrap().encode(), raw);
+
+        let mut sprite = fields(&[5, 6, 1, 3, 1]);
+        sprite.extend(fields(&[1, 0, 1, 2]));
+        sprite.extend([8, 9]);
+        sprite.extend(fields(&[0xFFFFFFFF; 4]));
+        assert_eq!(untyped("SPRITE_PIC", sprite.clone()).get_pic().unwrap().encode(), sprite);
+    }
+
+    #[test]
+    fn argb_is_checked() {
+        let black = ArgbPixel { alpha: 255, red: 0, green: 0, blue: 0 };
+        let palette = Palette { filename: "PALETTE_DAT".to_owned(), palette: vec![black; 2] };
+
+        let pic = Pic::new("A_PIC", 2, 1, vec![Some(1), None]);
+        assert_eq!(pic.try_get_argb(&palette), Ok(vec![black, ArgbPixel { alpha: 0, red: 0, green: 0, blue: 0 }]));
+
+        let pic = Pic::new("A_PIC", 2, 1, vec![Some(1), Some(2)]);
+        assert_eq!(pic.try_get_argb(&palette), Err(GlbError::ColorOutOfPalette { filename: "A_PIC".to_owned(), color: 2 }));
+
+        let mut bytes = fields(&[1, 1, 0, 100, 100]);
+        bytes.extend([0, 1, 0]);
+        let pic = untyped("SHORT_PIC", bytes).get_pic().unwrap();
+        assert_eq!(pic.try_get_argb(&palette), Err(GlbError::PixelCountMismatch { filename: "SHORT_PIC".to_owned(), pixels: 3, width: 100, height: 100 }));
+    }
+
+    #[test]
+    fn damaged_file_decodes_as_raw() {
+        let file = untyped("BROKEN_PIC", fields(&[1, 1]));
+
+        assert_eq!(file.decode(), File::Raw(file.get_raw()));
+        assert_eq!(file.decode_strict(), Err(GlbError::UnexpectedEof { filename: "BROKEN_PIC".to_owned(), offset: 8 }));
+    }
+
+    #[test]
+    fn huge_sprite_is_rejected() {
+        let mut bytes = fields(&[0, 0, 1, 0xFFFFFFFF, 0xFFFFFFFF]);
+        bytes.extend(fields(&[0, 0, 0xFFFFFFFF, 0xFFFFFFFF]));
+
+        let result = untyped("HUGE_PIC", bytes).get_pic();
+        assert_eq!(result, Err(GlbError::PixelOutOfBounds { filename: "HUGE_PIC".to_owned(), offset: PIC_HEADER_SIZE }));
+    }
+}
@@ -1,18 +1,28 @@
-use super::bytes::Bytes;
-use super::extracted::Extracted;
+use super::bytes::{decrypt, encrypt, ByteCursor};
+#[cfg(feature = "std")]
+use super::extracted::{Extracted, ExtractedEntry};
 use super::file::*;
+use super::error::GlbError;
 
-use std::collections::HashMap;
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Key used by the game, archives are opened with it unless told otherwise.
 pub const ENCRYPTION_KEY: &[u8; 8] = b"32768GLB";
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Flag {
     Normal,
     Encrypted,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FatEntry {
     pub filename: String,
     pub flag: Flag,
@@ -21,146 +31,417 @@ pub struct FatEntry {
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FileAllocationTable {
     pub entries: Vec<FatEntry>,
 }
 
+impl FileAllocationTable {
+
+    /// Finds first entry with given filename.
+    pub fn get(&self, filename: &str) -> Option<&FatEntry> {
+        self.entries.iter().find(|e| e.filename == filename)
+    }
+}
 
+
+/// Bytes of whole GLB archive. By default they are held in memory,
+/// with `mmap` feature they can be also memory mapped, see `GlbArchive::map_file`.
 #[derive(Debug, PartialEq)]
-pub struct GlbArchive {
-    pub bytes: Vec<u8>
+pub struct GlbArchive<B = Vec<u8>> {
+    pub bytes: B,
+
+    /// Key the FAT and encrypted files are encrypted with,
+    /// `ENCRYPTION_KEY` unless the archive was opened with another one.
+    pub key: Vec<u8>,
 }
 
 
-// The game only decrypts one FAT entry at a time, so after 28 bytes
-// the key and "previous byte read" must be set back to the initial state.
-const CHUNK_SIZE: usize = 28;
+/// The game only decrypts one FAT entry at a time, so after 28 bytes
+/// the key and "previous byte read" must be set back to the initial state.
+pub const CHUNK_SIZE: usize = 28;
 
 
 // If given first 28 bytes of file it parses them as header,
 // that means that offset field is interpreted as number of files,
 // which is value returned, and other fields are ignored.
-fn parse_header(encrypted_bytes: &mut [u8]) -> usize {
-    let entry = parse_fat_entry(encrypted_bytes);
-    entry.offset as usize
+pub(crate) fn parse_header(encrypted_bytes: &[u8], key: &[u8]) -> Result<usize, GlbError> {
+    let entry = parse_fat_entry(encrypted_bytes, 0, key)?;
+    Ok(entry.offset as usize)
 }
 
-// Parses single 28 byte File Allocation Table entry.
-fn parse_fat_entry(encrypted_bytes: &mut [u8]) -> FatEntry {
+// Parses single 28 byte File Allocation Table entry,
+// archive_offset is position of the entry used in error reporting.
+pub(crate) fn parse_fat_entry(encrypted_bytes: &[u8], archive_offset: usize, key: &[u8]) -> Result<FatEntry, GlbError> {
 
     if encrypted_bytes.len() != CHUNK_SIZE {
-        // TODO: Instead of panics we should return Result
-        panic!("You should decrypt by chunks of 28 bytes.");
+        return Err(GlbError::InvalidChunkSize { offset: archive_offset, size: encrypted_bytes.len() });
     }
 
-    let mut decrypted_bytes = Bytes::from(encrypted_bytes);
-    decrypted_bytes.decrypt(ENCRYPTION_KEY);
+    let mut chunk = [0u8; CHUNK_SIZE];
+    chunk.copy_from_slice(encrypted_bytes);
+    decrypt(&mut chunk, key);
+
+    let filename = chunk[12..28].split(|b| *b == 0).next().unwrap_or_default();
+    let filename = core::str::from_utf8(filename)
+        .map_err(|_| GlbError::InvalidFilename { offset: archive_offset + 12 })?
+        .to_string();
 
-    let mut offset: usize = 0;
+    // Chunk size was checked above, so the fixed size fields can't run out of bytes.
+    let mut cursor = ByteCursor::new(&chunk, &filename);
+    let flag = cursor.read_u32()?;
+    let file_offset = cursor.read_u32()?;
+    let length = cursor.read_u32()?;
 
-    let flag = match decrypted_bytes.read_u32(&mut offset) {
+    let flag = match flag {
         0 => Flag::Normal,
         1 => Flag::Encrypted,
-        other => panic!("Unknown flag {}", other)
+        other => return Err(GlbError::UnknownFlag { filename, offset: archive_offset, flag: other })
+    };
+
+    Ok(FatEntry { flag, offset: file_offset, length, filename })
+}
+
+// Encodes and encrypts single 28 byte File Allocation Table entry,
+// inverse of parse_fat_entry. Filename is truncated to 16 bytes.
+pub(crate) fn write_fat_entry(entry: &FatEntry, key: &[u8]) -> [u8; CHUNK_SIZE] {
+
+    let flag: u32 = match entry.flag {
+        Flag::Normal => 0,
+        Flag::Encrypted => 1,
+    };
+
+    let mut chunk = [0u8; CHUNK_SIZE];
+    chunk[0..4].copy_from_slice(&flag.to_le_bytes());
+    chunk[4..8].copy_from_slice(&entry.offset.to_le_bytes());
+    chunk[8..12].copy_from_slice(&entry.length.to_le_bytes());
+
+    let filename = entry.filename.as_bytes();
+    let filename_length = filename.len().min(CHUNK_SIZE - 12);
+    chunk[12..12 + filename_length].copy_from_slice(&filename[..filename_length]);
+
+    encrypt(&mut chunk, key);
+    chunk
+}
+
+// Returns 28 bytes of FAT starting at offset, or error if archive is too short.
+fn fat_chunk(bytes: &[u8], offset: usize) -> Result<&[u8], GlbError> {
+    let size = bytes.len().saturating_sub(offset).min(CHUNK_SIZE);
+    bytes.get(offset..offset + CHUNK_SIZE)
+        .ok_or(GlbError::InvalidChunkSize { offset, size })
+}
+
+// Checks whether the archive decrypts into something that looks like GLB:
+// FAT fits into the archive and the first entry has known flag, ASCII name
+// and data inside the archive.
+fn is_key_sane(bytes: &[u8], key: &[u8]) -> bool {
+
+    let count = match fat_chunk(bytes, 0).and_then(|chunk| parse_header(chunk, key)) {
+        Ok(count) => count,
+        Err(_) => return false,
     };
 
-    let file_offset = decrypted_bytes.read_u32(&mut offset);
+    let fat_end = match count.checked_add(1).and_then(|n| n.checked_mul(CHUNK_SIZE)) {
+        Some(fat_end) if fat_end <= bytes.len() => fat_end,
+        _ => return false,
+    };
+
+    if count == 0 {
+        return true;
+    }
 
-    let length = decrypted_bytes.read_u32(&mut offset);
+    // Flag is checked by parse_fat_entry.
+    let entry = match parse_fat_entry(&bytes[CHUNK_SIZE..2 * CHUNK_SIZE], CHUNK_SIZE, key) {
+        Ok(entry) => entry,
+        Err(_) => return false,
+    };
 
-    let filename = decrypted_bytes[12..28].split(|b| *b == 0).into_iter().next().unwrap();
-    let filename = core::str::from_utf8(filename).unwrap().to_string();
+    let data_end = match (entry.offset as usize).checked_add(entry.length as usize) {
+        Some(data_end) => data_end,
+        None => return false,
+    };
+
+    entry.filename.bytes().all(|b| b.is_ascii_graphic() || b == b' ')
+        && entry.offset as usize >= fat_end
+        && data_end <= bytes.len()
+}
 
-    FatEntry { flag, offset: file_offset, length, filename }
+/// Returns first of the candidate keys the archive can be decrypted with.
+/// Key is accepted when the header gives entry count that fits into the archive
+/// and the first FAT entry has flag 0 or 1, ASCII filename and data inside the archive.
+pub fn detect_key<'k>(bytes: &[u8], candidates: &[&'k [u8]]) -> Option<&'k [u8]> {
+    candidates.iter().copied().find(|key| is_key_sane(bytes, key))
 }
 
+#[cfg(feature = "std")]
 impl GlbArchive {
 
     pub fn from_file(path: &str) -> Option<GlbArchive> {
-        std::fs::read(path).ok().map(|bytes|
-            GlbArchive { bytes }
-        )
+        std::fs::read(path).ok().map(GlbArchive::new)
     }
+}
 
-    pub fn parse_fat(&mut self) -> FileAllocationTable {
+impl<B> GlbArchive<B> {
 
-        let mut offset: usize = 0;
-        let fat_entries_count = parse_header(&mut self.bytes[offset..offset+CHUNK_SIZE]);
-        let mut entries = Vec::with_capacity(fat_entries_count);
+    /// Archive encrypted with `ENCRYPTION_KEY`.
+    pub fn new(bytes: B) -> GlbArchive<B> {
+        GlbArchive::with_key(bytes, ENCRYPTION_KEY)
+    }
 
-        for _ in 0..fat_entries_count {
-            offset = offset + CHUNK_SIZE;
-            let entry = parse_fat_entry(&mut self.bytes[offset..offset+CHUNK_SIZE]);
-            entries.push(entry);
-        }
+    /// Archive encrypted with another key than the one used by the game.
+    pub fn with_key(bytes: B, key: &[u8]) -> GlbArchive<B> {
+        GlbArchive { bytes, key: key.to_vec() }
+    }
+}
+
+impl<B: AsRef<[u8]>> GlbArchive<B> {
 
-        FileAllocationTable { entries }
+    /// Archive encrypted with one of the candidate keys, see `detect_key`.
+    /// Returns `GlbError::UnknownKey` if none of them fits.
+    pub fn with_detected_key(bytes: B, candidates: &[&[u8]]) -> Result<GlbArchive<B>, GlbError> {
+        match detect_key(bytes.as_ref(), candidates) {
+            Some(key) => Ok(GlbArchive::with_key(bytes, key)),
+            None => Err(GlbError::UnknownKey),
+        }
     }
+}
 
-    pub fn extract_files(&mut self, fat: &FileAllocationTable) -> Extracted {
+#[cfg(feature = "mmap")]
+impl GlbArchive<memmap2::Mmap> {
+
+    /// Maps the file into memory instead of reading it. Files that are not encrypted
+    /// can be then accessed without copying, see `GlbArchive::file_bytes`.
+    ///
+    /// The file must not be modified while it's mapped, otherwise the content
+    /// of the archive changes under our hands.
+    pub fn map_file(path: &str) -> Result<GlbArchive<memmap2::Mmap>, GlbError> {
+        let file = std::fs::File::open(path)?;
+        let bytes = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(GlbArchive::new(bytes))
+    }
+}
 
-        let mut named_files: HashMap<String, File> = HashMap::with_capacity(fat.entries.len());
+impl<B: AsRef<[u8]>> GlbArchive<B> {
 
-        let mut tiles: Vec<Pic> = Vec::new();
+    #[cfg(feature = "std")]
+    pub fn to_file(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.bytes.as_ref())
+    }
 
-        let mut currently_reading_tiles = false;
+    /// Returns content of the file. Files that are not encrypted are borrowed
+    /// straight from the archive, encrypted files are decrypted into new buffer.
+    pub fn file_bytes(&self, entry: &FatEntry) -> Result<Cow<'_, [u8]>, GlbError> {
+        Ok(UntypedFile::read_file(self, entry)?.into_cow())
+    }
 
-        for entry in &fat.entries {
-            let untyped_file = UntypedFile::read_file(self, entry);
-            let filename = &untyped_file.filename;
+    /// Iterates over FAT entries, decrypting each entry only when it's reached.
+    pub fn entries(&self) -> Result<Entries<'_>, GlbError> {
+        let bytes = self.bytes.as_ref();
+        let count = parse_header(fat_chunk(bytes, 0)?, &self.key)?;
+        Ok(Entries { bytes, key: &self.key, index: 0, count })
+    }
 
-            if filename.ends_with("TXT")
-            {
-                let text = untyped_file.get_txt();
-                if let Some(t) = text {
-                    named_files.insert(filename.to_owned(), File::Text(t));
-                }
+    /// Finds first entry with given filename without parsing the rest of FAT.
+    pub fn entry(&self, filename: &str) -> Result<Option<ArchiveEntry<'_>>, GlbError> {
+        for entry in self.entries()? {
+            let entry = entry?;
+            if entry.fat_entry.filename == filename {
+                return Ok(Some(entry));
             }
-            else if filename.ends_with("_DAT")
-            {
-                let palette = untyped_file.get_dat();
-                if let Some(p) = palette {
-                    named_files.insert(filename.to_owned(), File::Palette(p));
-                }
-            }
-            else if filename.ends_with("_PIC")   ||
-                    filename.ends_with("_PIC//") ||
-                    filename.ends_with("_BLK")
-            {
-                let pic = untyped_file.get_pic();
-                if let Some(p) = pic {
-                    named_files.insert(filename.to_owned(), File::Pic(p));
-                }
-            }
-            else if filename.ends_with("_MAP")
-            {
-                let map = untyped_file.get_map();
-                if let Some(m) = map {
-                    named_files.insert(filename.to_owned(), File::Map(m));
-                }
-            }
-            else if filename.starts_with("STARTG")
-            {
+        }
+        Ok(None)
+    }
+
+    /// Decrypts and decodes only the file with given filename.
+    /// Returns None if there is no such file.
+    pub fn get_file(&self, filename: &str) -> Result<Option<File>, GlbError> {
+        match self.entry(filename)? {
+            Some(entry) => entry.decode().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn parse_fat(&self) -> Result<FileAllocationTable, GlbError> {
+        let entries = self.entries()?
+            .map(|entry| entry.map(|e| e.fat_entry))
+            .collect::<Result<Vec<FatEntry>, GlbError>>()?;
+
+        Ok(FileAllocationTable { entries })
+    }
+
+    /// Decrypts and decodes all files. With `parallel` feature files are decoded
+    /// concurrently, the result is the same as when they are decoded one by one.
+    #[cfg(feature = "std")]
+    pub fn extract_files(&self, fat: &FileAllocationTable) -> Result<Extracted, GlbError> {
+
+        // Tiles are unnamed files between STARTG and ENDG markers, which can be
+        // told only from the order of entries, so they are found before decoding.
+        let mut currently_reading_tiles = false;
+        let is_tile: Vec<bool> = fat.entries.iter().map(|entry| {
+            let filename = &entry.filename;
+            if filename.starts_with("STARTG") {
                 currently_reading_tiles = true;
-            }
-            else if filename == "" && currently_reading_tiles
-            {
-                let pic = untyped_file.get_pic();
-                if let Some(p) = pic {
-                    tiles.push(p);
-                }
-            }
-            else if filename.starts_with("ENDG")
-            {
+            } else if filename.starts_with("ENDG") {
                 currently_reading_tiles = false;
             }
-            else
-            {
-                // println!("Can't parse unknown file {}!", filename);
+            filename.is_empty() && currently_reading_tiles
+        }).collect();
+
+        let bytes = self.bytes.as_ref();
+        let key = self.key.as_slice();
+        let extract = |(index, entry): (usize, &FatEntry)| extract_entry(bytes, key, index, entry, is_tile[index]);
+
+        #[cfg(not(feature = "parallel"))]
+        let entries = fat.entries.iter().enumerate().map(extract).collect::<Result<Vec<ExtractedEntry>, GlbError>>()?;
+
+        #[cfg(feature = "parallel")]
+        let entries = fat.entries.par_iter().enumerate().map(extract).collect::<Result<Vec<ExtractedEntry>, GlbError>>()?;
+
+        let tiles = entries.iter()
+            .filter(|e| is_tile[e.index])
+            .filter_map(|e| match &e.file {
+                File::Pic(pic) => Some(pic.clone()),
+                _ => None,
+            })
+            .collect();
+
+        Ok(Extracted::new(entries, Tiles { tiles }))
+    }
+}
+
+#[cfg(feature = "std")]
+fn extract_entry(archive_bytes: &[u8], key: &[u8], index: usize, entry: &FatEntry, is_tile: bool) -> Result<ExtractedEntry, GlbError> {
+
+    let untyped_file = UntypedFile::read_slice(archive_bytes, entry, key)?;
+
+    // Tiles have to be recognized before the content
+    // of unnamed files is used to guess their type.
+    let file = if is_tile {
+        untyped_file.get_pic().map(File::Pic).unwrap_or_else(|_| File::Raw(untyped_file.get_raw()))
+    } else {
+        untyped_file.decode()
+    };
+
+    Ok(ExtractedEntry { index, fat_entry: entry.clone(), file })
+}
+
+/// Single FAT entry together with the archive it belongs to,
+/// file itself is read only when requested.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArchiveEntry<'a> {
+    archive_bytes: &'a [u8],
+    key: &'a [u8],
+
+    /// Position of the entry in FAT, header not included.
+    pub index: usize,
+
+    pub fat_entry: FatEntry,
+}
+
+impl<'a> ArchiveEntry<'a> {
+
+    /// Reads and decrypts the file.
+    pub fn read(&self) -> Result<UntypedFile<'a>, GlbError> {
+        UntypedFile::read_slice(self.archive_bytes, &self.fat_entry, self.key)
+    }
+
+    /// Reads, decrypts and decodes the file, see `UntypedFile::decode`.
+    pub fn decode(&self) -> Result<File, GlbError> {
+        Ok(self.read()?.decode())
+    }
+}
+
+/// Iterator over FAT entries returned by `GlbArchive::entries`.
+/// Iteration stops after first error.
+#[derive(Debug, Clone)]
+pub struct Entries<'a> {
+    bytes: &'a [u8],
+    key: &'a [u8],
+    index: usize,
+    count: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<ArchiveEntry<'a>, GlbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.count {
+            return None;
+        }
+
+        let index = self.index;
+        let offset = (index + 1) * CHUNK_SIZE;
+
+        let fat_entry = fat_chunk(self.bytes, offset)
+            .and_then(|chunk| parse_fat_entry(chunk, offset, self.key));
+
+        match fat_entry {
+            Ok(fat_entry) => {
+                self.index += 1;
+                Some(Ok(ArchiveEntry { archive_bytes: self.bytes, key: self.key, index, fat_entry }))
+            }
+            Err(e) => {
+                self.index = self.count;
+                Some(Err(e))
             }
         }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.count - self.index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glb_writer::GlbArchiveBuilder;
+    use alloc::vec;
+
+    const MOD_KEY: &[u8] = b"Another key of a mod";
 
-        let tiles = Tiles { tiles };
-        Extracted { named_files, tiles }
+    fn archive(key: &[u8]) -> Vec<u8> {
+        let mut builder = GlbArchiveBuilder::with_key(key);
+        builder.add("FIRST_TXT", Flag::Normal, b"first".to_vec()).unwrap();
+        builder.add("SECOND_TXT", Flag::Encrypted, b"second".to_vec()).unwrap();
+        builder.build().unwrap().bytes
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn right_key_is_picked() {
+        let bytes = archive(MOD_KEY);
+        let candidates: [&[u8]; 3] = [ENCRYPTION_KEY, b"XYZ", MOD_KEY];
+
+        assert_eq!(detect_key(&bytes, &candidates), Some(MOD_KEY));
+        assert_eq!(detect_key(&archive(ENCRYPTION_KEY), &candidates), Some(&ENCRYPTION_KEY[..]));
+
+        let archive = GlbArchive::with_detected_key(bytes, &candidates).unwrap();
+        assert_eq!(archive.key, MOD_KEY);
+
+        let fat = archive.parse_fat().unwrap();
+        assert_eq!(archive.file_bytes(&fat.entries[1]).unwrap().as_ref(), b"second");
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let bytes = archive(MOD_KEY);
+        assert!(!is_key_sane(&bytes, ENCRYPTION_KEY));
+        assert!(!is_key_sane(&bytes, &MOD_KEY[1..]));
+        assert!(is_key_sane(&bytes, MOD_KEY));
+    }
+
+    #[test]
+    fn no_match_is_unknown_key() {
+        let candidates: [&[u8]; 2] = [ENCRYPTION_KEY, b"XYZ"];
+
+        assert_eq!(detect_key(&archive(MOD_KEY), &candidates), None);
+        assert_eq!(GlbArchive::with_detected_key(archive(MOD_KEY), &candidates), Err(GlbError::UnknownKey));
+        assert_eq!(GlbArchive::with_detected_key(vec![0; 10], &candidates), Err(GlbError::UnknownKey));
+    }
+
+    #[test]
+    fn empty_archive_has_sane_key() {
+        let bytes = GlbArchiveBuilder::with_key(MOD_KEY).build().unwrap().bytes;
+        assert_eq!(detect_key(&bytes, &[MOD_KEY]), Some(MOD_KEY));
+    }
+}
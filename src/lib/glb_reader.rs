@@ -0,0 +1,103 @@
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::vec::Vec;
+
+use super::error::GlbError;
+use super::file::*;
+use super::glb_archive::*;
+
+/// Reads GLB archive from any seekable source.
+///
+/// Only the header and FAT are read when the reader is created,
+/// each file is read from its offset only when it's requested.
+#[derive(Debug)]
+pub struct GlbReader<R> {
+    reader: R,
+    fat: FileAllocationTable,
+    key: Vec<u8>,
+}
+
+// Reads up to 28 bytes, fewer bytes are returned only at the end of data.
+fn read_chunk<R: Read>(reader: &mut R) -> Result<Vec<u8>, GlbError> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    reader.take(CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+impl<R: Read + Seek> GlbReader<R> {
+
+    /// Reads archive encrypted with `ENCRYPTION_KEY`.
+    pub fn new(reader: R) -> Result<GlbReader<R>, GlbError> {
+        GlbReader::with_key(reader, ENCRYPTION_KEY)
+    }
+
+    /// Reads archive encrypted with another key than the one used by the game.
+    pub fn with_key(mut reader: R, key: &[u8]) -> Result<GlbReader<R>, GlbError> {
+
+        reader.seek(SeekFrom::Start(0))?;
+
+        let fat_entries_count = parse_header(&read_chunk(&mut reader)?, key)?;
+
+        // Entries are read one by one, so broken header can't make us allocate huge FAT up front.
+        let mut entries = Vec::new();
+        let mut offset: usize = 0;
+
+        for _ in 0..fat_entries_count {
+            offset += CHUNK_SIZE;
+            let entry = parse_fat_entry(&read_chunk(&mut reader)?, offset, key)?;
+            entries.push(entry);
+        }
+
+        Ok(GlbReader { reader, fat: FileAllocationTable { entries }, key: key.to_vec() })
+    }
+
+    pub fn fat(&self) -> &FileAllocationTable {
+        &self.fat
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Seeks to the file and reads it, decrypting it if necessary.
+    pub fn read_file(&mut self, entry: &FatEntry) -> Result<UntypedFile<'static>, GlbError> {
+
+        self.reader.seek(SeekFrom::Start(entry.offset as u64))?;
+
+        let mut bytes = Vec::new();
+        (&mut self.reader).take(entry.length as u64).read_to_end(&mut bytes)?;
+
+        if bytes.len() != entry.length as usize {
+            return Err(GlbError::EntryOutOfBounds {
+                filename: entry.filename.clone(),
+                offset: entry.offset as usize,
+                length: entry.length as usize,
+            });
+        }
+
+        Ok(UntypedFile::from_vec(entry, bytes, &self.key))
+    }
+
+    /// Reads and decodes only the file with given filename.
+    /// Returns None if there is no such file.
+    pub fn get_file(&mut self, filename: &str) -> Result<Option<File>, GlbError> {
+        match self.fat.get(filename).cloned() {
+            Some(entry) => Ok(Some(self.read_file(&entry)?.decode())),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'a> GlbReader<Cursor<&'a [u8]>> {
+
+    /// Reads archive that is already in memory.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<GlbReader<Cursor<&'a [u8]>>, GlbError> {
+        GlbReader::new(Cursor::new(bytes))
+    }
+}
+
+impl GlbReader<BufReader<std::fs::File>> {
+
+    pub fn from_file(path: &str) -> Result<GlbReader<BufReader<std::fs::File>>, GlbError> {
+        GlbReader::new(BufReader::new(std::fs::File::open(path)?))
+    }
+}
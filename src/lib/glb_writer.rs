@@ -0,0 +1,379 @@
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use super::bytes::{decrypt, encrypt};
+use super::error::GlbError;
+use super::glb_archive::*;
+
+// Length of filename field in FAT entry, last byte is kept for NUL terminator.
+const MAX_FILENAME_LENGTH: usize = 15;
+
+fn check_filename(filename: &str) -> Result<(), GlbError> {
+    if filename.len() > MAX_FILENAME_LENGTH || !filename.bytes().all(|b| b.is_ascii() && b != 0) {
+        return Err(GlbError::UnsupportedFilename { filename: filename.to_owned() });
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct PendingFile {
+    filename: String,
+    flag: Flag,
+    bytes: Vec<u8>,
+}
+
+/// Collects files and lays them out into new GLB archive.
+///
+/// Files are stored in the order they were added, directly after the FAT,
+/// and files flagged as `Flag::Encrypted` are encrypted with `ENCRYPTION_KEY`
+/// or the key given to `GlbArchiveBuilder::with_key`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GlbArchiveBuilder {
+    files: Vec<PendingFile>,
+    key: Vec<u8>,
+}
+
+impl Default for GlbArchiveBuilder {
+    fn default() -> GlbArchiveBuilder {
+        GlbArchiveBuilder::new()
+    }
+}
+
+impl GlbArchiveBuilder {
+
+    pub fn new() -> GlbArchiveBuilder {
+        GlbArchiveBuilder::with_key(ENCRYPTION_KEY)
+    }
+
+    /// Builder of archive encrypted with another key than the one used by the game.
+    pub fn with_key(key: &[u8]) -> GlbArchiveBuilder {
+        GlbArchiveBuilder { files: Vec::new(), key: key.to_vec() }
+    }
+
+    /// Appends file to the archive. Filename may be empty, which is used
+    /// for tiles between STARTG and ENDG markers.
+    pub fn add(&mut self, filename: &str, flag: Flag, bytes: Vec<u8>) -> Result<(), GlbError> {
+
+        check_filename(filename)?;
+
+        self.files.push(PendingFile { filename: filename.to_owned(), flag, bytes });
+        Ok(())
+    }
+
+    pub fn build(&self) -> Result<GlbArchive, GlbError> {
+
+        // Header is stored as first FAT entry, followed by one entry per file.
+        let fat_length = (self.files.len() + 1) * CHUNK_SIZE;
+        let data_length: usize = self.files.iter().map(|f| f.bytes.len()).sum();
+
+        let mut bytes: Vec<u8> = Vec::with_capacity(fat_length + data_length);
+
+        let header = FatEntry {
+            filename: String::new(),
+            flag: Flag::Normal,
+            offset: self.files.len() as u32,
+            length: 0,
+        };
+        bytes.extend_from_slice(&write_fat_entry(&header, &self.key));
+
+        let mut offset = fat_length;
+        for file in &self.files {
+
+            let too_large = || GlbError::ArchiveTooLarge { filename: file.filename.clone() };
+
+            let entry = FatEntry {
+                filename: file.filename.clone(),
+                flag: file.flag,
+                offset: u32::try_from(offset).map_err(|_| too_large())?,
+                length: u32::try_from(file.bytes.len()).map_err(|_| too_large())?,
+            };
+            bytes.extend_from_slice(&write_fat_entry(&entry, &self.key));
+
+            offset += file.bytes.len();
+        }
+
+        if u32::try_from(offset).is_err() {
+            return Err(GlbError::ArchiveTooLarge { filename: String::new() });
+        }
+
+        for file in &self.files {
+            let start = bytes.len();
+            bytes.extend_from_slice(&file.bytes);
+
+            if file.flag == Flag::Encrypted {
+                encrypt(&mut bytes[start..], &self.key);
+            }
+        }
+
+        Ok(GlbArchive::with_key(bytes, &self.key))
+    }
+}
+
+// Byte positions of fields inside decrypted FAT entry.
+const OFFSET_FIELD: usize = 4;
+const LENGTH_FIELD: usize = 8;
+
+// Rewrites single field of encrypted FAT entry in place. The rest of the entry,
+// including whatever follows NUL in filename, stays as it was.
+fn patch_fat_entry(chunk: &mut [u8], field: usize, value: u32, key: &[u8]) {
+    decrypt(chunk, key);
+    chunk[field..field + 4].copy_from_slice(&value.to_le_bytes());
+    encrypt(chunk, key);
+}
+
+// Whether data of entry at FAT position `ix` lies behind position `at`,
+// where data of entry at FAT position `index` starts. Empty entries,
+// which share position with their neighbours, are ordered by FAT.
+fn is_behind(entry: &FatEntry, ix: usize, at: usize, index: usize) -> bool {
+    let offset = entry.offset as usize;
+    offset > at || (offset == at && ix > index)
+}
+
+fn moved(entry: &FatEntry, by: i64) -> Result<u32, GlbError> {
+    u32::try_from(entry.offset as i64 + by)
+        .map_err(|_| GlbError::ArchiveTooLarge { filename: entry.filename.clone() })
+}
+
+/// Edits of single files, which keep the rest of the archive byte-identical
+/// except for offsets in FAT that have to move. Files are expected not to overlap.
+impl GlbArchive {
+
+    /// Replaces content of the first file with given filename, keeping its flag.
+    pub fn replace_entry(&mut self, filename: &str, bytes: Vec<u8>) -> Result<(), GlbError> {
+
+        let fat = self.parse_fat()?;
+        let key = self.key.clone();
+        let (index, entry) = find_entry(&fat, filename)?;
+        let (start, end) = self.data_range(entry)?;
+
+        let mut data = bytes;
+        if entry.flag == Flag::Encrypted {
+            encrypt(&mut data, &self.key);
+        }
+
+        let length = u32::try_from(data.len())
+            .map_err(|_| GlbError::ArchiveTooLarge { filename: filename.to_owned() })?;
+        let delta = data.len() as i64 - (end - start) as i64;
+
+        let mut offsets = Vec::with_capacity(fat.entries.len());
+        for (ix, e) in fat.entries.iter().enumerate() {
+            let by = if ix != index && is_behind(e, ix, start, index) { delta } else { 0 };
+            offsets.push(moved(e, by)?);
+        }
+        self.check_size(delta, filename)?;
+
+        self.bytes.splice(start..end, data);
+
+        patch_fat_entry(self.fat_entry_mut(index), LENGTH_FIELD, length, &key);
+        for (ix, offset) in offsets.into_iter().enumerate() {
+            if offset != fat.entries[ix].offset {
+                patch_fat_entry(self.fat_entry_mut(ix), OFFSET_FIELD, offset, &key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Inserts file at given position of FAT, its data are stored in front of data
+    /// of the file currently at that position, or at the end of the archive.
+    pub fn insert_entry(&mut self, index: usize, filename: &str, flag: Flag, bytes: Vec<u8>) -> Result<(), GlbError> {
+
+        check_filename(filename)?;
+
+        let fat = self.parse_fat()?;
+        let key = self.key.clone();
+        let index = index.min(fat.entries.len());
+
+        let at = match fat.entries.get(index) {
+            Some(entry) => self.data_range(entry)?.0,
+            None => self.bytes.len(),
+        };
+
+        let mut data = bytes;
+        if flag == Flag::Encrypted {
+            encrypt(&mut data, &self.key);
+        }
+
+        let too_large = || GlbError::ArchiveTooLarge { filename: filename.to_owned() };
+
+        // Everything moves by the new FAT entry, files behind also by the new data.
+        let mut offsets = Vec::with_capacity(fat.entries.len() + 1);
+        for (ix, e) in fat.entries.iter().enumerate() {
+            let behind = e.offset as usize > at || (e.offset as usize == at && ix >= index);
+            let by = CHUNK_SIZE as i64 + if behind { data.len() as i64 } else { 0 };
+            offsets.push(moved(e, by)?);
+        }
+        self.check_size((CHUNK_SIZE + data.len()) as i64, filename)?;
+
+        let new_entry = FatEntry {
+            filename: filename.to_owned(),
+            flag,
+            offset: u32::try_from(at + CHUNK_SIZE).map_err(|_| too_large())?,
+            length: u32::try_from(data.len()).map_err(|_| too_large())?,
+        };
+
+        self.bytes.splice(at..at, data);
+        let record_offset = (index + 1) * CHUNK_SIZE;
+        self.bytes.splice(record_offset..record_offset, write_fat_entry(&new_entry, &self.key).iter().copied());
+
+        patch_fat_entry(&mut self.bytes[0..CHUNK_SIZE], OFFSET_FIELD, (fat.entries.len() + 1) as u32, &key);
+        for (ix, offset) in offsets.into_iter().enumerate() {
+            // Entries from index on moved one place down in FAT.
+            let ix_now = if ix >= index { ix + 1 } else { ix };
+            patch_fat_entry(self.fat_entry_mut(ix_now), OFFSET_FIELD, offset, &key);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the first file with given filename together with its data.
+    pub fn remove_entry(&mut self, filename: &str) -> Result<(), GlbError> {
+
+        let fat = self.parse_fat()?;
+        let key = self.key.clone();
+        let (index, entry) = find_entry(&fat, filename)?;
+        let (start, end) = self.data_range(entry)?;
+
+        let length = (end - start) as i64;
+
+        let mut offsets = Vec::with_capacity(fat.entries.len());
+        for (ix, e) in fat.entries.iter().enumerate() {
+            let by = -(CHUNK_SIZE as i64) - if is_behind(e, ix, start, index) { length } else { 0 };
+            offsets.push(if ix == index { 0 } else { moved(e, by)? });
+        }
+
+        self.bytes.drain(start..end);
+        let record_offset = (index + 1) * CHUNK_SIZE;
+        self.bytes.drain(record_offset..record_offset + CHUNK_SIZE);
+
+        patch_fat_entry(&mut self.bytes[0..CHUNK_SIZE], OFFSET_FIELD, (fat.entries.len() - 1) as u32, &key);
+        for (ix, offset) in offsets.into_iter().enumerate() {
+            if ix != index {
+                let ix_now = if ix > index { ix - 1 } else { ix };
+                patch_fat_entry(self.fat_entry_mut(ix_now), OFFSET_FIELD, offset, &key);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Encrypted bytes of FAT entry at given position, header not included.
+    fn fat_entry_mut(&mut self, index: usize) -> &mut [u8] {
+        let offset = (index + 1) * CHUNK_SIZE;
+        &mut self.bytes[offset..offset + CHUNK_SIZE]
+    }
+
+    fn data_range(&self, entry: &FatEntry) -> Result<(usize, usize), GlbError> {
+        let start = entry.offset as usize;
+        match start.checked_add(entry.length as usize) {
+            Some(end) if end <= self.bytes.len() => Ok((start, end)),
+            _ => Err(GlbError::EntryOutOfBounds { filename: entry.filename.clone(), offset: start, length: entry.length as usize }),
+        }
+    }
+
+    // FAT offsets can't address archive larger than 4 GiB.
+    fn check_size(&self, delta: i64, filename: &str) -> Result<(), GlbError> {
+        if u32::try_from(self.bytes.len() as i64 + delta).is_err() {
+            return Err(GlbError::ArchiveTooLarge { filename: filename.to_owned() });
+        }
+        Ok(())
+    }
+}
+
+fn find_entry<'a>(fat: &'a FileAllocationTable, filename: &str) -> Result<(usize, &'a FatEntry), GlbError> {
+    fat.entries.iter().enumerate()
+        .find(|(_, e)| e.filename == filename)
+        .ok_or_else(|| GlbError::EntryNotFound { filename: filename.to_owned() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn archive() -> GlbArchive {
+        let mut builder = GlbArchiveBuilder::new();
+        builder.add("FIRST_TXT", Flag::Normal, b"first file".to_vec()).unwrap();
+        builder.add("SECOND_DAT", Flag::Encrypted, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        builder.add("", Flag::Normal, Vec::new()).unwrap();
+        builder.add("THIRD_PIC", Flag::Encrypted, (0..=255).collect()).unwrap();
+        builder.build().unwrap()
+    }
+
+    // Decrypted content and stored bytes of every file, by filename.
+    fn files(archive: &GlbArchive) -> Vec<(String, Vec<u8>, Vec<u8>)> {
+        let fat = archive.parse_fat().unwrap();
+        assert!(archive.validate().is_valid(), "{:?}", archive.validate());
+        fat.entries.iter()
+            .map(|e| {
+                let stored = archive.bytes[e.offset as usize..(e.offset + e.length) as usize].to_vec();
+                (e.filename.clone(), archive.file_bytes(e).unwrap().into_owned(), stored)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn build_stores_files_in_order() {
+        let archive = archive();
+        let names: Vec<String> = files(&archive).into_iter().map(|f| f.0).collect();
+        assert_eq!(names, ["FIRST_TXT", "SECOND_DAT", "", "THIRD_PIC"]);
+        assert_eq!(files(&archive)[1].1, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn replace_keeps_other_files() {
+        let mut archive = archive();
+        let before = files(&archive);
+
+        archive.replace_entry("SECOND_DAT", b"longer content than before".to_vec()).unwrap();
+        let after = files(&archive);
+
+        assert_eq!(after[1].1, b"longer content than before");
+        for ix in [0, 2, 3] {
+            assert_eq!(after[ix], before[ix]);
+        }
+    }
+
+    #[test]
+    fn insert_keeps_other_files() {
+        let mut archive = archive();
+        let before = files(&archive);
+
+        archive.insert_entry(1, "NEW_TXT", Flag::Encrypted, b"inserted".to_vec()).unwrap();
+        archive.insert_entry(100, "LAST_TXT", Flag::Normal, b"appended".to_vec()).unwrap();
+        let after = files(&archive);
+
+        assert_eq!(after.len(), 6);
+        assert_eq!((after[1].0.as_str(), after[1].1.as_slice()), ("NEW_TXT", &b"inserted"[..]));
+        assert_eq!((after[5].0.as_str(), after[5].1.as_slice()), ("LAST_TXT", &b"appended"[..]));
+        assert_eq!(after[0], before[0]);
+        assert_eq!(&after[2..5], &before[1..4]);
+    }
+
+    #[test]
+    fn remove_keeps_other_files() {
+        let mut archive = archive();
+        let before = files(&archive);
+
+        archive.remove_entry("SECOND_DAT").unwrap();
+        let after = files(&archive);
+
+        assert_eq!(after, [before[0].clone(), before[2].clone(), before[3].clone()]);
+        assert!(archive.remove_entry("SECOND_DAT").is_err());
+    }
+
+    #[test]
+    fn replace_with_same_length_changes_only_its_data() {
+        let mut archive = archive();
+        let data_start = (files(&archive).len() + 1) * CHUNK_SIZE;
+        let before = archive.bytes.clone();
+
+        // Same length, so nothing moves and only the data of the file change.
+        archive.replace_entry("FIRST_TXT", b"FIRST FILE".to_vec()).unwrap();
+        assert_eq!(archive.bytes[..data_start], before[..data_start]);
+        assert_eq!(archive.bytes.len(), before.len());
+        assert_eq!(archive.bytes[data_start + 10..], before[data_start + 10..]);
+    }
+}
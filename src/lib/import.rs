@@ -0,0 +1,370 @@
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use image::RgbaImage;
+
+use super::error::GlbError;
+use super::file::{ArgbPixel, Palette, Pic};
+
+/// How colors that are not in the palette are approximated.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Dithering {
+    /// Every pixel gets the nearest color.
+    #[default]
+    None,
+
+    /// Difference from the nearest color is spread to the neighbouring pixels.
+    FloydSteinberg,
+
+    /// Pixels are shifted by 4x4 Bayer matrix before the nearest color is found.
+    Ordered,
+}
+
+/// Options of `Pic::from_image` and `Pic::from_png`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ImportOptions {
+    pub dithering: Dithering,
+
+    /// Palette indices that are never used for approximated colors,
+    /// such as colors the game cycles or reserves for effects.
+    pub reserved: Vec<u8>,
+}
+
+// Bayer matrix for ordered dithering, values 0 to 15.
+const BAYER: [[i32; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
+
+// Largest shift of channel by ordered dithering is about half of this value.
+const ORDERED_SPREAD: i32 = 32;
+
+// Palette indices that may be used for approximated colors.
+struct Candidates<'a> {
+    palette: &'a [ArgbPixel],
+    indices: Vec<u8>,
+}
+
+impl<'a> Candidates<'a> {
+    fn new(palette: &'a Palette, options: &ImportOptions) -> Candidates<'a> {
+        let indices = (0..palette.palette.len().min(256))
+            .map(|ix| ix as u8)
+            .filter(|ix| !options.reserved.contains(ix))
+            .collect();
+        Candidates { palette: &palette.palette, indices }
+    }
+
+    // Index of the color with the least squared distance, the first one wins ties.
+    fn nearest(&self, rgb: [i32; 3]) -> u8 {
+        let mut best = self.indices[0];
+        let mut best_distance = i32::MAX;
+        for &ix in &self.indices {
+            let color = rgb_of(self.palette[ix as usize]);
+            let distance = (0..3).map(|c| (rgb[c] - color[c]).pow(2)).sum();
+            if distance < best_distance {
+                best = ix;
+                best_distance = distance;
+            }
+        }
+        best
+    }
+}
+
+fn rgb_of(pixel: ArgbPixel) -> [i32; 3] {
+    [pixel.red as i32, pixel.green as i32, pixel.blue as i32]
+}
+
+impl Pic {
+
+    /// Converts image to picture with colors of given palette, inverse of `Pic::to_imagebuffer`.
+    /// Fully transparent pixels become `None`, other pixels are opaque and get the nearest
+    /// color that is not reserved. Returns `GlbError::EmptyPalette` if there is no such color.
+    pub fn from_image(filename: &str, image: &RgbaImage, palette: &Palette, options: &ImportOptions) -> Result<Pic, GlbError> {
+
+        let candidates = Candidates::new(palette, options);
+        if candidates.indices.is_empty() {
+            return Err(GlbError::EmptyPalette { filename: filename.to_string() });
+        }
+
+        let width = image.width() as usize;
+        let height = image.height() as usize;
+
+        let mut pixels: Vec<Option<u8>> = Vec::with_capacity(width * height);
+
+        // Errors diffused into the current and the next row, with one pixel of margin on both sides.
+        let mut errors = vec![[0i32; 3]; width + 2];
+        let mut next_errors = vec![[0i32; 3]; width + 2];
+
+        for y in 0..height {
+            for x in 0..width {
+
+                let pixel = image.get_pixel(x as u32, y as u32);
+                if pixel[3] == 0 {
+                    pixels.push(None);
+                    continue;
+                }
+
+                let mut rgb = [pixel[0] as i32, pixel[1] as i32, pixel[2] as i32];
+
+                match options.dithering {
+                    Dithering::None => {}
+                    Dithering::FloydSteinberg => {
+                        for (c, value) in rgb.iter_mut().enumerate() {
+                            *value = (*value + errors[x + 1][c] / 16).clamp(0, 255);
+                        }
+                    }
+                    Dithering::Ordered => {
+                        let shift = (BAYER[y % 4][x % 4] * 2 - 15) * ORDERED_SPREAD / 32;
+                        for value in rgb.iter_mut() {
+                            *value = (*value + shift).clamp(0, 255);
+                        }
+                    }
+                }
+
+                let index = candidates.nearest(rgb);
+                pixels.push(Some(index));
+
+                if options.dithering == Dithering::FloydSteinberg {
+                    let color = rgb_of(palette.palette[index as usize]);
+                    for c in 0..3 {
+                        let error = rgb[c] - color[c];
+                        errors[x + 2][c] += error * 7;
+                        next_errors[x][c] += error * 3;
+                        next_errors[x + 1][c] += error * 5;
+                        next_errors[x + 2][c] += error;
+                    }
+                }
+            }
+
+            core::mem::swap(&mut errors, &mut next_errors);
+            next_errors.iter_mut().for_each(|e| *e = [0; 3]);
+        }
+
+        Ok(Pic::new(filename, width, height, pixels))
+    }
+
+    /// Decodes PNG and converts it to picture with colors of given palette.
+    /// Indexed PNG whose colors are all in the palette is mapped exactly,
+    /// without approximation and dithering, other PNGs are converted by `Pic::from_image`.
+    /// Exact mapping ignores `ImportOptions::reserved`, so colors the PNG uses keep
+    /// their index even if it's reserved.
+    pub fn from_png(filename: &str, png: &[u8], palette: &Palette, options: &ImportOptions) -> Result<Pic, GlbError> {
+
+        let invalid = |e: &dyn core::fmt::Display| GlbError::InvalidImage { filename: filename.to_string(), message: e.to_string() };
+
+        if let Some(pic) = from_indexed_png(filename, png, palette).map_err(|e| invalid(&e))? {
+            return Ok(pic);
+        }
+
+        let image = image::load_from_memory_with_format(png, image::ImageFormat::Png)
+            .map_err(|e| invalid(&e))?
+            .to_rgba8();
+
+        Pic::from_image(filename, &image, palette, options)
+    }
+}
+
+// Maps indexed PNG to the palette, returns None if it's not indexed
+// or it uses a color that is not in the palette.
+fn from_indexed_png(filename: &str, png: &[u8], palette: &Palette) -> Result<Option<Pic>, png::DecodingError> {
+
+    let mut decoder = png::Decoder::new(png);
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let (output, mut reader) = decoder.read_info()?;
+
+    if output.color_type != png::ColorType::Indexed {
+        return Ok(None);
+    }
+
+    let info = reader.info();
+    let png_palette: Vec<[u8; 3]> = match &info.palette {
+        Some(colors) => colors.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+        None => return Ok(None),
+    };
+
+    // Entries missing from tRNS chunk are opaque.
+    let transparent: Vec<bool> = (0..png_palette.len())
+        .map(|ix| info.trns.as_ref().and_then(|t| t.get(ix)) == Some(&0))
+        .collect();
+
+    // The same index is preferred, so duplicate colors of the palette keep their index.
+    let same = |ix: usize, color: [u8; 3]| palette.palette.get(ix)
+        .filter(|p| [p.red, p.green, p.blue] == color)
+        .map(|_| ix);
+    let mapping: Vec<Option<u8>> = png_palette.iter().enumerate()
+        .map(|(ix, &color)| same(ix, color)
+            .or_else(|| palette.palette.iter().position(|p| [p.red, p.green, p.blue] == color))
+            .filter(|ix| *ix < 256)
+            .map(|ix| ix as u8))
+        .collect();
+
+    let mut data = vec![0; output.buffer_size()];
+    reader.next_frame(&mut data)?;
+
+    let width = output.width as usize;
+    let height = output.height as usize;
+    let bits = output.bit_depth as usize;
+
+    let mut pixels: Vec<Option<u8>> = Vec::with_capacity(width * height);
+
+    for row in data.chunks(output.line_size).take(height) {
+        for x in 0..width {
+            let bit = x * bits;
+            let byte = match row.get(bit / 8) {
+                Some(byte) => *byte as usize,
+                None => return Ok(None),
+            };
+            let index = (byte >> (8 - bits - bit % 8)) & ((1 << bits) - 1);
+
+            match (transparent.get(index), mapping.get(index)) {
+                (Some(true), _) => pixels.push(None),
+                (Some(false), Some(Some(mapped))) => pixels.push(Some(*mapped)),
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    Ok(Some(Pic::new(filename, width, height, pixels)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::borrow::ToOwned;
+    use image::Rgba;
+
+    const BLACK: [u8; 3] = [0, 0, 0];
+    const RED: [u8; 3] = [252, 0, 0];
+    const GREEN: [u8; 3] = [0, 252, 0];
+    const BLUE: [u8; 3] = [0, 0, 252];
+    const WHITE: [u8; 3] = [252, 252, 252];
+
+    fn palette(colors: &[[u8; 3]]) -> Palette {
+        let palette = colors.iter().map(|c| ArgbPixel { alpha: 255, red: c[0], green: c[1], blue: c[2] }).collect();
+        Palette { filename: "PALETTE_DAT".to_owned(), palette }
+    }
+
+    fn indexed_png(width: u32, colors: &[[u8; 3]], trns: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width, data.len() as u32 / width);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(colors.concat());
+        if !trns.is_empty() {
+            encoder.set_trns(trns.to_vec());
+        }
+        encoder.write_header().unwrap().write_image_data(data).unwrap();
+        png
+    }
+
+    fn image(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba(color))
+    }
+
+    fn reserved(indices: &[u8]) -> ImportOptions {
+        ImportOptions { reserved: indices.to_vec(), ..ImportOptions::default() }
+    }
+
+    #[test]
+    fn indexed_png_is_mapped_exactly() {
+        let game = palette(&[BLACK, RED, GREEN, BLUE]);
+        let png = indexed_png(2, &[RED, GREEN, BLUE], &[255, 0], &[0, 1, 2, 0]);
+
+        let pic = Pic::from_png("A_PIC", &png, &game, &ImportOptions::default()).unwrap();
+        assert_eq!((pic.width, pic.height), (2, 2));
+        assert_eq!(pic.pixels, [Some(1), None, Some(3), Some(1)]);
+    }
+
+    #[test]
+    fn indexed_png_keeps_index_of_duplicate_color() {
+        let colors = [BLACK, RED, GREEN, RED];
+        let png = indexed_png(4, &colors, &[], &[0, 1, 2, 3]);
+
+        let pic = Pic::from_png("A_PIC", &png, &palette(&colors), &ImportOptions::default()).unwrap();
+        assert_eq!(pic.pixels, [Some(0), Some(1), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn indexed_png_ignores_reserved() {
+        let game = palette(&[BLACK, RED]);
+        let png = indexed_png(1, &[RED], &[], &[0]);
+
+        let pic = Pic::from_png("A_PIC", &png, &game, &reserved(&[1])).unwrap();
+        assert_eq!(pic.pixels, [Some(1)]);
+    }
+
+    #[test]
+    fn indexed_png_with_other_colors_is_approximated() {
+        let game = palette(&[BLACK, RED]);
+        let png = indexed_png(1, &[[200, 10, 10]], &[], &[0]);
+
+        let pic = Pic::from_png("A_PIC", &png, &game, &ImportOptions::default()).unwrap();
+        assert_eq!(pic.pixels, [Some(1)]);
+    }
+
+    #[test]
+    fn invalid_png() {
+        let result = Pic::from_png("A_PIC", b"not a png", &palette(&[BLACK]), &ImportOptions::default());
+        assert!(matches!(result, Err(GlbError::InvalidImage { .. })));
+    }
+
+    #[test]
+    fn transparent_pixels_become_none() {
+        let mut image = image(2, 1, [252, 0, 0, 255]);
+        image.put_pixel(1, 0, Rgba([252, 0, 0, 0]));
+
+        let pic = Pic::from_image("A_PIC", &image, &palette(&[BLACK, RED]), &ImportOptions::default()).unwrap();
+        assert_eq!(pic.pixels, [Some(1), None]);
+    }
+
+    #[test]
+    fn nearest_color_is_chosen() {
+        let image = image(1, 1, [10, 200, 20, 255]);
+
+        let pic = Pic::from_image("A_PIC", &image, &palette(&[BLACK, RED, GREEN, BLUE]), &ImportOptions::default()).unwrap();
+        assert_eq!(pic.pixels, [Some(2)]);
+    }
+
+    #[test]
+    fn reserved_indices_are_never_chosen() {
+        let game = palette(&[BLACK, RED, GREEN, BLUE, RED]);
+        let image = image(4, 4, [252, 0, 0, 255]);
+
+        for dithering in [Dithering::None, Dithering::FloydSteinberg, Dithering::Ordered] {
+            let options = ImportOptions { dithering, reserved: vec![1, 4] };
+            let pic = Pic::from_image("A_PIC", &image, &game, &options).unwrap();
+            assert!(pic.pixels.iter().all(|p| matches!(p, Some(0) | Some(2) | Some(3))), "{:?}", dithering);
+        }
+    }
+
+    #[test]
+    fn all_reserved_is_empty_palette() {
+        let result = Pic::from_image("A_PIC", &image(1, 1, [0, 0, 0, 255]), &palette(&[BLACK, RED]), &reserved(&[0, 1]));
+        assert_eq!(result, Err(GlbError::EmptyPalette { filename: "A_PIC".to_owned() }));
+
+        let result = Pic::from_image("A_PIC", &image(1, 1, [0, 0, 0, 255]), &palette(&[]), &ImportOptions::default());
+        assert_eq!(result, Err(GlbError::EmptyPalette { filename: "A_PIC".to_owned() }));
+    }
+
+    #[test]
+    fn dithering_mixes_colors() {
+        let game = palette(&[BLACK, WHITE]);
+        let gray = image(8, 8, [126, 126, 126, 255]);
+
+        let count_white = |dithering| {
+            let options = ImportOptions { dithering, reserved: Vec::new() };
+            let pic = Pic::from_image("A_PIC", &gray, &game, &options).unwrap();
+            pic.pixels.iter().filter(|p| **p == Some(1)).count()
+        };
+
+        // Without dithering the gray is a single color, with dithering it's about half and half.
+        assert!(count_white(Dithering::None) == 0 || count_white(Dithering::None) == 64);
+        for dithering in [Dithering::FloydSteinberg, Dithering::Ordered] {
+            let white = count_white(dithering);
+            assert!((24..=40).contains(&white), "{:?} gives {} white pixels", dithering, white);
+        }
+    }
+}
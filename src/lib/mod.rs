@@ -1,8 +1,48 @@
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+extern crate std;
+
 mod bytes;
+mod detect;
+#[cfg(feature = "std")]
+mod diff;
 mod file;
 mod glb_archive;
+#[cfg(feature = "std")]
+mod glb_reader;
+mod glb_writer;
+mod palette;
+#[cfg(feature = "image")]
+mod import;
+mod validate;
+#[cfg(feature = "std")]
+mod vfs;
+#[cfg(feature = "std")]
 mod extracted;
+mod error;
 
+pub use bytes::{decrypt, decrypt_chunks, encrypt, encrypt_chunks, ByteCursor};
+#[cfg(feature = "std")]
+pub use diff::{diff, ArchiveDiff, CellChange, ContentChange, EntryChange, LineChange, Region};
+pub use detect::{detect, FileKind, Guess, DETECT_THRESHOLD};
 pub use file::*;
-pub use glb_archive::GlbArchive;
-pub use glb_archive::ENCRYPTION_KEY;
\ No newline at end of file
+pub use error::GlbError;
+#[cfg(feature = "std")]
+pub use extracted::{Extracted, ExtractedEntry};
+pub use glb_archive::{ArchiveEntry, Entries, GlbArchive};
+pub use glb_archive::{FatEntry, FileAllocationTable, Flag};
+pub use glb_archive::{detect_key, CHUNK_SIZE, ENCRYPTION_KEY};
+#[cfg(feature = "std")]
+pub use glb_reader::GlbReader;
+pub use glb_writer::GlbArchiveBuilder;
+#[cfg(feature = "image")]
+pub use import::{Dithering, ImportOptions};
+pub use validate::{Issue, ValidationReport};
+#[cfg(feature = "std")]
+pub use vfs::{GlbVfs, Shadowed, VfsEntry};
+
+#[cfg(feature = "mmap")]
+pub use memmap2::Mmap;
\ No newline at end of file
@@ -0,0 +1,241 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::file::{ArgbPixel, Palette};
+
+// VGA DAC has 6 bits per channel, palettes store it scaled to 0-255.
+const DAC_MAX: i32 = 63;
+
+// Nearest DAC value, exact inverse of the scaling done by `UntypedFile::get_dat`.
+fn to_dac(value: u8) -> i32 {
+    (value as i32 * DAC_MAX + 127) / 255
+}
+
+fn from_dac(value: i32) -> u8 {
+    (value.clamp(0, DAC_MAX) * 255 / DAC_MAX) as u8
+}
+
+fn map_rgb(pixel: ArgbPixel, f: impl Fn(i32) -> i32) -> ArgbPixel {
+    ArgbPixel {
+        alpha: pixel.alpha,
+        red: from_dac(f(to_dac(pixel.red))),
+        green: from_dac(f(to_dac(pixel.green))),
+        blue: from_dac(f(to_dac(pixel.blue))),
+    }
+}
+
+/// Palette effects used by the game for fades and color cycling.
+/// Colors are computed in 6bit DAC values the same way VGA would show them,
+/// every effect returns new palette and alpha of colors is kept.
+impl Palette {
+
+    /// Palette at `step` of `steps` on the way to `target`, step 0 is this palette unchanged
+    /// and step `steps` is the target. Colors missing in the target are kept. Colors of other
+    /// steps are rounded to DAC values, which changes palettes that weren't read from the game.
+    pub fn fade_step(&self, target: &Palette, step: u32, steps: u32) -> Palette {
+        if step == 0 {
+            return self.clone();
+        }
+
+        let step = step.min(steps) as i32;
+        let steps = steps.max(1) as i32;
+
+        let palette = self.palette.iter().enumerate()
+            .map(|(ix, &from)| match target.palette.get(ix) {
+                Some(&to) => ArgbPixel {
+                    alpha: from.alpha,
+                    red: fade_channel(from.red, to.red, step, steps),
+                    green: fade_channel(from.green, to.green, step, steps),
+                    blue: fade_channel(from.blue, to.blue, step, steps),
+                },
+                None => from,
+            })
+            .collect();
+
+        Palette { filename: self.filename.clone(), palette }
+    }
+
+    /// Fades to `target` over `steps` frames, the last palette is the target rounded to DAC values.
+    pub fn fade_to(&self, target: &Palette, steps: u32) -> Vec<Palette> {
+        (1..=steps).map(|step| self.fade_step(target, step, steps)).collect()
+    }
+
+    /// Fades to black over `steps` frames.
+    pub fn fade_to_black(&self, steps: u32) -> Vec<Palette> {
+        self.fade_to(&self.filled(0), steps)
+    }
+
+    /// Fades to white over `steps` frames.
+    pub fn fade_to_white(&self, steps: u32) -> Vec<Palette> {
+        self.fade_to(&self.filled(255), steps)
+    }
+
+    /// Multiplies DAC values of colors by `numerator / denominator`, colors saturate
+    /// at white. Denominator 0 is treated as 1.
+    pub fn scale(&self, numerator: u32, denominator: u32) -> Palette {
+        let numerator = numerator.min(i32::MAX as u32) as i64;
+        let denominator = denominator.clamp(1, i32::MAX as u32) as i64;
+
+        let palette = self.palette.iter()
+            .map(|&pixel| map_rgb(pixel, |c| (c as i64 * numerator / denominator).min(DAC_MAX as i64) as i32))
+            .collect();
+
+        Palette { filename: self.filename.clone(), palette }
+    }
+
+    /// Brightens colors by `percent`, 100 doubles them.
+    pub fn brighten(&self, percent: u32) -> Palette {
+        self.scale(percent.saturating_add(100), 100)
+    }
+
+    /// Darkens colors by `percent`, 100 and more gives black.
+    pub fn darken(&self, percent: u32) -> Palette {
+        self.scale(100u32.saturating_sub(percent), 100)
+    }
+
+    /// Rotates colors in `range` of indices by `shift`, color at index `i`
+    /// moves to `i + shift` and colors pushed out of the range wrap around.
+    /// Parts of the range outside of the palette are ignored.
+    pub fn rotate(&self, range: Range<usize>, shift: isize) -> Palette {
+        let mut palette = self.palette.clone();
+
+        let end = range.end.min(palette.len());
+        let start = range.start.min(end);
+        let colors = &mut palette[start..end];
+
+        if !colors.is_empty() {
+            let shift = shift.rem_euclid(colors.len() as isize) as usize;
+            colors.rotate_right(shift);
+        }
+
+        Palette { filename: self.filename.clone(), palette }
+    }
+
+    fn filled(&self, value: u8) -> Palette {
+        let color = ArgbPixel { alpha: 255, red: value, green: value, blue: value };
+        Palette { filename: self.filename.clone(), palette: alloc::vec![color; self.palette.len()] }
+    }
+}
+
+fn fade_channel(from: u8, to: u8, step: i32, steps: i32) -> u8 {
+    let from = to_dac(from);
+    let to = to_dac(to);
+    from_dac(from + (to - from) * step / steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::borrow::ToOwned;
+
+    // Same scaling as `UntypedFile::get_dat`.
+    fn dac(value: u8) -> u8 {
+        (value as u32 * 255 / 63) as u8
+    }
+
+    fn palette(colors: &[(u8, u8, u8)]) -> Palette {
+        let palette = colors.iter()
+            .map(|&(red, green, blue)| ArgbPixel { alpha: 255, red, green, blue })
+            .collect();
+        Palette { filename: "TEST_DAT".to_owned(), palette }
+    }
+
+    fn reds(palette: &Palette) -> Vec<u8> {
+        palette.palette.iter().map(|pixel| pixel.red).collect()
+    }
+
+    #[test]
+    fn dac_round_trip() {
+        for value in 0..=DAC_MAX {
+            assert_eq!(from_dac(value), dac(value as u8));
+            assert_eq!(to_dac(from_dac(value)), value);
+        }
+
+        assert_eq!(to_dac(0), 0);
+        assert_eq!(to_dac(255), DAC_MAX);
+        assert_eq!(to_dac(2), 0);
+        assert_eq!(to_dac(3), 1);
+        assert_eq!(from_dac(-5), 0);
+        assert_eq!(from_dac(DAC_MAX + 5), 255);
+    }
+
+    #[test]
+    fn fade_step_ends() {
+        // Green 128 isn't a DAC value, step 0 must keep it.
+        let from = palette(&[(dac(10), 128, dac(63)), (dac(20), dac(30), dac(40))]);
+        let to = palette(&[(dac(50), dac(0), dac(5)), (dac(1), dac(2), dac(3))]);
+
+        assert_eq!(from.fade_step(&to, 0, 8), from);
+        assert_eq!(from.fade_step(&to, 8, 8), to);
+        assert_eq!(from.fade_step(&to, 20, 8), to);
+
+        let half = from.fade_step(&to, 1, 2);
+        assert_eq!(half.palette[0].red, dac(30));
+        // Steps are rounded towards the starting color.
+        assert_eq!(half.palette[1].blue, dac(22));
+    }
+
+    #[test]
+    fn fade_step_keeps_missing_colors() {
+        let from = palette(&[(dac(10), dac(10), dac(10)), (dac(20), dac(20), dac(20))]);
+        let to = palette(&[(0, 0, 0)]);
+
+        let faded = from.fade_step(&to, 4, 4);
+        assert_eq!(faded.palette[0], to.palette[0]);
+        assert_eq!(faded.palette[1], from.palette[1]);
+    }
+
+    #[test]
+    fn fade_to_black_ends_at_black() {
+        let mut from = palette(&[(255, 128, 7), (dac(40), dac(41), dac(42))]);
+        from.palette[1].alpha = 0;
+
+        let frames = from.fade_to_black(5);
+        assert_eq!(frames.len(), 5);
+
+        let last = frames.last().unwrap();
+        assert_eq!(last.palette[0], ArgbPixel { alpha: 255, red: 0, green: 0, blue: 0 });
+        assert_eq!(last.palette[1], ArgbPixel { alpha: 0, red: 0, green: 0, blue: 0 });
+
+        // Every frame is darker than the one before.
+        for pair in frames.windows(2) {
+            assert!(pair[1].palette[0].red < pair[0].palette[0].red);
+        }
+
+        assert!(from.fade_to_black(0).is_empty());
+    }
+
+    #[test]
+    fn scale_saturates() {
+        let from = palette(&[(dac(40), dac(20), dac(0))]);
+
+        assert_eq!(from.scale(2, 1), palette(&[(255, dac(40), 0)]));
+        assert_eq!(from.scale(1, 2), palette(&[(dac(20), dac(10), 0)]));
+        assert_eq!(from.scale(u32::MAX, 1), palette(&[(255, 255, 0)]));
+        assert_eq!(from.scale(3, 0), from.scale(3, 1));
+
+        assert_eq!(from.brighten(100), from.scale(2, 1));
+        assert_eq!(from.darken(100), palette(&[(0, 0, 0)]));
+        assert_eq!(from.darken(150), palette(&[(0, 0, 0)]));
+    }
+
+    #[test]
+    fn rotate_shifts() {
+        let from = palette(&[(0, 0, 0), (1, 0, 0), (2, 0, 0), (3, 0, 0), (4, 0, 0)]);
+
+        assert_eq!(reds(&from.rotate(1..4, 1)), [0, 3, 1, 2, 4]);
+        assert_eq!(reds(&from.rotate(1..4, -1)), [0, 2, 3, 1, 4]);
+        assert_eq!(reds(&from.rotate(0..5, 7)), reds(&from.rotate(0..5, 2)));
+        assert_eq!(reds(&from.rotate(0..5, -7)), reds(&from.rotate(0..5, 3)));
+    }
+
+    #[test]
+    fn rotate_past_palette() {
+        let from = palette(&[(0, 0, 0), (1, 0, 0), (2, 0, 0), (3, 0, 0), (4, 0, 0)]);
+
+        assert_eq!(reds(&from.rotate(3..10, 1)), [0, 1, 2, 4, 3]);
+        assert_eq!(reds(&from.rotate(3..10, -3)), [0, 1, 2, 4, 3]);
+        assert_eq!(from.rotate(10..20, 1), from);
+        assert_eq!(from.rotate(2..2, 1), from);
+    }
+}
@@ -0,0 +1,336 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::bytes::{decrypt, ByteCursor};
+use super::glb_archive::*;
+
+/// Problem found in archive by `GlbArchive::validate`.
+/// Indexes are positions of entries in FAT, header not included.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Issue {
+    /// Archive is too short to contain header.
+    MissingHeader { archive_length: usize },
+
+    /// Header declares more FAT entries than fit into archive.
+    FatOutOfBounds { count: usize, fat_end: usize, archive_length: usize },
+
+    /// FAT continues past the start of the first file.
+    FatOverlapsData { fat_end: usize, data_offset: u32 },
+
+    /// File ends past the end of archive.
+    EntryOutOfBounds { index: usize, filename: String, offset: u32, length: u32, archive_length: usize },
+
+    /// Two files share some bytes.
+    Overlap { first: usize, second: usize, offset: u32, length: u32 },
+
+    /// Bytes that don't belong to FAT nor to any file.
+    Gap { offset: usize, length: usize },
+
+    /// Filename contains bytes that are not printable ASCII.
+    NonAsciiFilename { index: usize, raw: Vec<u8> },
+
+    /// All 16 bytes of filename are used, so it's not terminated by NUL.
+    UnterminatedFilename { index: usize, raw: Vec<u8> },
+
+    /// Flag is neither 0 (normal) nor 1 (encrypted).
+    UnknownFlag { index: usize, filename: String, flag: u32 },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::MissingHeader { archive_length } =>
+                write!(f, "archive of {} bytes is too short to contain header", archive_length),
+            Issue::FatOutOfBounds { count, fat_end, archive_length } =>
+                write!(f, "FAT of {} entries ends at {}, past the end of archive at {}", count, fat_end, archive_length),
+            Issue::FatOverlapsData { fat_end, data_offset } =>
+                write!(f, "FAT ends at {}, but first file starts at {}", fat_end, data_offset),
+            Issue::EntryOutOfBounds { index, filename, offset, length, archive_length } =>
+                write!(f, "entry {} {:?} of {} bytes at {} ends past the end of archive at {}", index, filename, length, offset, archive_length),
+            Issue::Overlap { first, second, offset, length } =>
+                write!(f, "entries {} and {} share {} bytes at {}", first, second, length, offset),
+            Issue::Gap { offset, length } =>
+                write!(f, "{} unused bytes at {}", length, offset),
+            Issue::NonAsciiFilename { index, raw } =>
+                write!(f, "entry {} has filename {:?} that is not ASCII", index, raw),
+            Issue::UnterminatedFilename { index, raw } =>
+                write!(f, "entry {} has filename {:?} that is not terminated", index, raw),
+            Issue::UnknownFlag { index, filename, flag } =>
+                write!(f, "entry {} {:?} has unknown flag {}", index, filename, flag),
+        }
+    }
+}
+
+/// Result of `GlbArchive::validate`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+// FAT entry decrypted without any checks, so all problems can be reported.
+struct RawEntry {
+    index: usize,
+    flag: u32,
+    offset: u32,
+    length: u32,
+    filename: Vec<u8>,
+}
+
+fn read_raw_entry(encrypted_bytes: &[u8], index: usize, key: &[u8]) -> RawEntry {
+    let mut chunk = [0u8; CHUNK_SIZE];
+    chunk.copy_from_slice(encrypted_bytes);
+    decrypt(&mut chunk, key);
+
+    // Chunk has all 28 bytes, so none of the reads can fail.
+    // Filename field follows the three numbers and ends with the entry.
+    let mut cursor = ByteCursor::new(&chunk, "");
+    let flag = cursor.read_u32().unwrap_or_default();
+    let offset = cursor.read_u32().unwrap_or_default();
+    let length = cursor.read_u32().unwrap_or_default();
+    let filename = cursor.read_rest().to_vec();
+
+    RawEntry { index, flag, offset, length, filename }
+}
+
+impl<B: AsRef<[u8]>> GlbArchive<B> {
+
+    /// Checks FAT for entries that point outside of the archive or overlap each other,
+    /// unused bytes, broken filenames and unknown flags. Unlike `parse_fat`
+    /// it doesn't stop at the first problem.
+    pub fn validate(&self) -> ValidationReport {
+
+        let bytes = self.bytes.as_ref();
+        let archive_length = bytes.len();
+        let mut issues = Vec::new();
+
+        if archive_length < CHUNK_SIZE {
+            issues.push(Issue::MissingHeader { archive_length });
+            return ValidationReport { issues };
+        }
+
+        let count = read_raw_entry(&bytes[0..CHUNK_SIZE], 0, &self.key).offset as usize;
+        let fat_end = count.saturating_add(1).saturating_mul(CHUNK_SIZE);
+
+        if fat_end > archive_length {
+            issues.push(Issue::FatOutOfBounds { count, fat_end, archive_length });
+        }
+
+        // Only entries that are actually present are checked.
+        let entries: Vec<RawEntry> = bytes[CHUNK_SIZE..fat_end.min(archive_length)]
+            .chunks_exact(CHUNK_SIZE)
+            .enumerate()
+            .map(|(index, chunk)| read_raw_entry(chunk, index, &self.key))
+            .collect();
+
+        for entry in &entries {
+            let filename = String::from_utf8_lossy(entry.filename.split(|b| *b == 0).next().unwrap_or_default()).into_owned();
+
+            if entry.flag > 1 {
+                issues.push(Issue::UnknownFlag { index: entry.index, filename: filename.clone(), flag: entry.flag });
+            }
+
+            if !entry.filename.contains(&0) {
+                issues.push(Issue::UnterminatedFilename { index: entry.index, raw: entry.filename.clone() });
+            }
+
+            if !filename.bytes().all(|b| (0x20..0x7F).contains(&b)) {
+                issues.push(Issue::NonAsciiFilename { index: entry.index, raw: entry.filename.clone() });
+            }
+
+            if (entry.offset as usize).saturating_add(entry.length as usize) > archive_length {
+                issues.push(Issue::EntryOutOfBounds {
+                    index: entry.index,
+                    filename,
+                    offset: entry.offset,
+                    length: entry.length,
+                    archive_length,
+                });
+            }
+        }
+
+        // Empty files, such as STARTG and ENDG markers, don't occupy any bytes.
+        let mut occupied: Vec<&RawEntry> = entries.iter()
+            .filter(|e| e.length > 0 && (e.offset as usize).saturating_add(e.length as usize) <= archive_length)
+            .collect();
+        occupied.sort_by_key(|e| (e.offset, e.index));
+
+        // Walks files by their offset, remembering the one that reaches furthest.
+        let mut covered_until = fat_end;
+
+        if let Some(first) = occupied.first() {
+            if (first.offset as usize) < fat_end {
+                issues.push(Issue::FatOverlapsData { fat_end, data_offset: first.offset });
+
+                // Files are then checked against each other only, not against the FAT.
+                covered_until = first.offset as usize;
+            }
+        }
+        let mut furthest: Option<&RawEntry> = None;
+
+        for entry in occupied {
+            let start = entry.offset as usize;
+            let end = start + entry.length as usize;
+
+            if start > covered_until {
+                issues.push(Issue::Gap { offset: covered_until, length: start - covered_until });
+            }
+
+            if let Some(previous) = furthest {
+                if start < covered_until {
+                    issues.push(Issue::Overlap {
+                        first: previous.index,
+                        second: entry.index,
+                        offset: entry.offset,
+                        length: (end.min(covered_until) - start) as u32,
+                    });
+                }
+            }
+
+            if end > covered_until || furthest.is_none() {
+                covered_until = covered_until.max(end);
+                furthest = Some(entry);
+            }
+        }
+
+        if archive_length > covered_until {
+            issues.push(Issue::Gap { offset: covered_until, length: archive_length - covered_until });
+        }
+
+        ValidationReport { issues }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytes::encrypt;
+    use crate::glb_writer::GlbArchiveBuilder;
+    use alloc::borrow::ToOwned;
+    use alloc::vec;
+
+    const FILENAME_LENGTH: usize = 16;
+
+    // Three normal files of 10 bytes at 112, 122 and 132.
+    fn archive() -> GlbArchive {
+        let mut builder = GlbArchiveBuilder::new();
+        for filename in ["A_TXT", "B_TXT", "C_TXT"] {
+            builder.add(filename, Flag::Normal, vec![b'x'; 10]).unwrap();
+        }
+        builder.build().unwrap()
+    }
+
+    fn record(flag: u32, offset: u32, length: u32, filename: &[u8]) -> Vec<u8> {
+        let mut chunk = [flag, offset, length].iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>();
+        chunk.extend(filename);
+        chunk.resize(CHUNK_SIZE, 0);
+        encrypt(&mut chunk, ENCRYPTION_KEY);
+        chunk
+    }
+
+    // Overwrites FAT entry at given position, header not included.
+    fn patch(archive: &mut GlbArchive, index: usize, flag: u32, offset: u32, length: u32, filename: &[u8]) {
+        let start = (index + 1) * CHUNK_SIZE;
+        archive.bytes[start..start + CHUNK_SIZE].copy_from_slice(&record(flag, offset, length, filename));
+    }
+
+    fn issues(archive: &GlbArchive) -> Vec<Issue> {
+        archive.validate().issues
+    }
+
+    #[test]
+    fn built_archive_is_valid() {
+        assert!(archive().validate().is_valid());
+    }
+
+    #[test]
+    fn missing_header() {
+        let archive = GlbArchive::new(vec![0; 10]);
+        assert_eq!(issues(&archive), [Issue::MissingHeader { archive_length: 10 }]);
+    }
+
+    #[test]
+    fn fat_out_of_bounds() {
+        let mut archive = GlbArchive::new(record(0, 100, 0, b""));
+        archive.bytes.extend(record(0, 0, 0, b""));
+        assert_eq!(issues(&archive), [Issue::FatOutOfBounds { count: 100, fat_end: 2828, archive_length: 56 }]);
+    }
+
+    #[test]
+    fn overlap() {
+        let mut archive = archive();
+        patch(&mut archive, 1, 0, 115, 10, b"B_TXT");
+
+        assert_eq!(issues(&archive), [
+            Issue::Overlap { first: 0, second: 1, offset: 115, length: 7 },
+            Issue::Gap { offset: 125, length: 7 },
+        ]);
+    }
+
+    #[test]
+    fn gap() {
+        let mut archive = archive();
+        patch(&mut archive, 1, 0, 122, 5, b"B_TXT");
+        archive.bytes.extend([0; 3]);
+
+        assert_eq!(issues(&archive), [
+            Issue::Gap { offset: 127, length: 5 },
+            Issue::Gap { offset: 142, length: 3 },
+        ]);
+    }
+
+    #[test]
+    fn fat_overlaps_data() {
+        let mut archive = archive();
+
+        // Header claims fourth entry, which is read from data of the files.
+        // The data are chosen so that the entry is empty file.
+        archive.bytes[112..140].copy_from_slice(&record(0, 0, 0, b""));
+        archive.bytes[..CHUNK_SIZE].copy_from_slice(&record(0, 4, 0, b""));
+
+        assert_eq!(issues(&archive), [Issue::FatOverlapsData { fat_end: 140, data_offset: 112 }]);
+    }
+
+    #[test]
+    fn entry_out_of_bounds() {
+        let mut archive = archive();
+        patch(&mut archive, 2, 0, 132, 0xFFFFFFFF, b"C_TXT");
+
+        assert_eq!(issues(&archive), [
+            Issue::EntryOutOfBounds { index: 2, filename: "C_TXT".to_owned(), offset: 132, length: 0xFFFFFFFF, archive_length: 142 },
+            Issue::Gap { offset: 132, length: 10 },
+        ]);
+    }
+
+    #[test]
+    fn unknown_flag() {
+        let mut archive = archive();
+        patch(&mut archive, 0, 2, 112, 10, b"A_TXT");
+
+        assert_eq!(issues(&archive), [Issue::UnknownFlag { index: 0, filename: "A_TXT".to_owned(), flag: 2 }]);
+    }
+
+    #[test]
+    fn broken_filenames() {
+        let mut archive = archive();
+        patch(&mut archive, 0, 0, 112, 10, b"A\xE9_TXT");
+        patch(&mut archive, 1, 0, 122, 10, b"0123456789ABCDEF");
+
+        let mut unterminated = b"0123456789ABCDEF".to_vec();
+        unterminated.truncate(FILENAME_LENGTH);
+        let mut non_ascii = b"A\xE9_TXT".to_vec();
+        non_ascii.resize(FILENAME_LENGTH, 0);
+
+        assert_eq!(issues(&archive), [
+            Issue::NonAsciiFilename { index: 0, raw: non_ascii },
+            Issue::UnterminatedFilename { index: 1, raw: unterminated },
+        ]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::borrow::ToOwned;
+use std::collections::HashMap;
+use std::string::String;
+use std::vec::Vec;
+
+use super::error::GlbError;
+use super::file::*;
+use super::glb_archive::*;
+
+#[derive(Debug, PartialEq)]
+struct Mount<B> {
+    name: String,
+    archive: GlbArchive<B>,
+    fat: FileAllocationTable,
+}
+
+/// Entry of one of the mounted archives.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct VfsEntry<'a> {
+
+    /// Position of the archive in mount order.
+    pub archive_index: usize,
+
+    /// Name the archive was mounted under.
+    pub archive_name: &'a str,
+
+    /// Position of the entry in FAT of its archive, header not included.
+    pub index: usize,
+
+    pub fat_entry: &'a FatEntry,
+}
+
+/// Entry that is hidden by entry with the same name from archive of higher priority,
+/// or by earlier entry with the same name in the same archive.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Shadowed<'a> {
+    pub entry: VfsEntry<'a>,
+    pub by: VfsEntry<'a>,
+}
+
+/// Several archives seen as one, such as FILE0000.GLB to FILE0004.GLB of the game
+/// together with mods. Archives mounted later take priority over those mounted before,
+/// so filename resolves to the entry from the last archive that contains it.
+///
+/// Unnamed entries, such as tiles, can't be resolved by name and are only reachable
+/// through FAT of their archive.
+#[derive(Debug, PartialEq)]
+pub struct GlbVfs<B = Vec<u8>> {
+    mounts: Vec<Mount<B>>,
+}
+
+impl<B: AsRef<[u8]>> Default for GlbVfs<B> {
+    fn default() -> GlbVfs<B> {
+        GlbVfs { mounts: Vec::new() }
+    }
+}
+
+impl<B: AsRef<[u8]>> GlbVfs<B> {
+
+    pub fn new() -> GlbVfs<B> {
+        GlbVfs::default()
+    }
+
+    /// Mounts archive with priority over all archives mounted before.
+    pub fn mount(&mut self, name: &str, archive: GlbArchive<B>) -> Result<(), GlbError> {
+        let fat = archive.parse_fat()?;
+        self.mounts.push(Mount { name: name.to_owned(), archive, fat });
+        Ok(())
+    }
+
+    /// Names of mounted archives, from lowest to highest priority.
+    pub fn archives(&self) -> impl Iterator<Item = &str> + '_ {
+        self.mounts.iter().map(|m| m.name.as_str())
+    }
+
+    pub fn archive(&self, archive_index: usize) -> Option<&GlbArchive<B>> {
+        self.mounts.get(archive_index).map(|m| &m.archive)
+    }
+
+    /// Finds the entry with given filename in archive of the highest priority.
+    pub fn resolve(&self, filename: &str) -> Option<VfsEntry<'_>> {
+        if filename.is_empty() {
+            return None;
+        }
+
+        self.mounts.iter().enumerate().rev().find_map(|(archive_index, mount)| {
+            mount.fat.entries.iter().position(|e| e.filename == filename)
+                .map(|index| self.entry(archive_index, index))
+        })
+    }
+
+    pub fn read_file(&self, entry: &VfsEntry<'_>) -> Result<UntypedFile<'_>, GlbError> {
+        UntypedFile::read_file(&self.mounts[entry.archive_index].archive, entry.fat_entry)
+    }
+
+    /// Decrypts and decodes the file with given filename from archive of the highest priority.
+    /// Returns None if no archive contains such file.
+    pub fn get_file(&self, filename: &str) -> Result<Option<File>, GlbError> {
+        match self.resolve(filename) {
+            Some(entry) => Ok(Some(self.read_file(&entry)?.decode())),
+            None => Ok(None),
+        }
+    }
+
+    /// All named entries that resolve to themselves, ordered by archive and FAT.
+    pub fn entries(&self) -> Vec<VfsEntry<'_>> {
+        let visible = self.visible();
+        self.named_entries()
+            .filter(|e| visible.get(e.fat_entry.filename.as_str()) == Some(e))
+            .collect()
+    }
+
+    /// All named entries that are hidden by other entry, ordered by archive and FAT.
+    pub fn shadowed(&self) -> Vec<Shadowed<'_>> {
+        let visible = self.visible();
+        self.named_entries()
+            .filter_map(|entry| {
+                let by = visible[entry.fat_entry.filename.as_str()];
+                if by == entry { None } else { Some(Shadowed { entry, by }) }
+            })
+            .collect()
+    }
+
+    fn entry(&self, archive_index: usize, index: usize) -> VfsEntry<'_> {
+        let mount = &self.mounts[archive_index];
+        VfsEntry {
+            archive_index,
+            archive_name: &mount.name,
+            index,
+            fat_entry: &mount.fat.entries[index],
+        }
+    }
+
+    fn named_entries(&self) -> impl Iterator<Item = VfsEntry<'_>> + '_ {
+        self.mounts.iter().enumerate().flat_map(move |(archive_index, mount)| {
+            mount.fat.entries.iter().enumerate()
+                .filter(|(_, e)| !e.filename.is_empty())
+                .map(move |(index, _)| self.entry(archive_index, index))
+        })
+    }
+
+    // Entry each filename resolves to.
+    fn visible(&self) -> HashMap<&str, VfsEntry<'_>> {
+        let mut visible = HashMap::new();
+        for entry in self.named_entries() {
+            let filename = entry.fat_entry.filename.as_str();
+            // Later archive wins, within one archive the first entry wins.
+            match visible.get(filename) {
+                Some(VfsEntry { archive_index, .. }) if *archive_index == entry.archive_index => {}
+                _ => { visible.insert(filename, entry); }
+            }
+        }
+        visible
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glb_writer::GlbArchiveBuilder;
+
+    fn archive(files: &[(&str, &str)]) -> GlbArchive {
+        let mut builder = GlbArchiveBuilder::new();
+        for (filename, text) in files {
+            builder.add(filename, Flag::Normal, text.as_bytes().to_vec()).unwrap();
+        }
+        builder.build().unwrap()
+    }
+
+    // Base archive with duplicate and unnamed entries, and mod replacing one of its files.
+    fn vfs() -> GlbVfs {
+        let mut vfs = GlbVfs::new();
+        vfs.mount("BASE", archive(&[("A_TXT", "base a"), ("B_TXT", "base b"), ("", "tile"), ("A_TXT", "second a")])).unwrap();
+        vfs.mount("MOD", archive(&[("B_TXT", "mod b"), ("", "mod tile"), ("C_TXT", "mod c")])).unwrap();
+        vfs
+    }
+
+    fn position(entry: &VfsEntry<'_>) -> (&'static str, usize) {
+        (if entry.archive_index == 0 { "BASE" } else { "MOD" }, entry.index)
+    }
+
+    fn text(vfs: &GlbVfs, filename: &str) -> String {
+        match vfs.get_file(filename).unwrap() {
+            Some(File::Text(text)) => text.text,
+            file => panic!("{:?}", file),
+        }
+    }
+
+    #[test]
+    fn later_archive_has_priority() {
+        let vfs = vfs();
+        assert_eq!(vfs.resolve("B_TXT").map(|e| position(&e)), Some(("MOD", 0)));
+        assert_eq!(vfs.resolve("C_TXT").map(|e| position(&e)), Some(("MOD", 2)));
+        assert_eq!(vfs.resolve("B_TXT").unwrap().archive_name, "MOD");
+        assert_eq!(text(&vfs, "B_TXT"), "mod b");
+        assert_eq!(vfs.get_file("D_TXT").unwrap(), None);
+    }
+
+    #[test]
+    fn first_duplicate_in_archive_wins() {
+        let vfs = vfs();
+        assert_eq!(vfs.resolve("A_TXT").map(|e| position(&e)), Some(("BASE", 0)));
+        assert_eq!(text(&vfs, "A_TXT"), "base a");
+    }
+
+    #[test]
+    fn unnamed_entries_are_excluded() {
+        let vfs = vfs();
+        assert_eq!(vfs.resolve(""), None);
+        assert!(vfs.entries().iter().all(|e| !e.fat_entry.filename.is_empty()));
+        assert!(vfs.shadowed().iter().all(|s| !s.entry.fat_entry.filename.is_empty()));
+    }
+
+    #[test]
+    fn entries_resolve_to_themselves() {
+        let vfs = vfs();
+        let entries: Vec<(&str, usize)> = vfs.entries().iter().map(position).collect();
+        assert_eq!(entries, [("BASE", 0), ("MOD", 0), ("MOD", 2)]);
+    }
+
+    #[test]
+    fn shadowed_entries() {
+        let vfs = vfs();
+        let shadowed = vfs.shadowed();
+        let pairs: Vec<_> = shadowed.iter().map(|s| (position(&s.entry), position(&s.by))).collect();
+        assert_eq!(pairs, [(("BASE", 1), ("MOD", 0)), (("BASE", 3), ("BASE", 0))]);
+    }
+}
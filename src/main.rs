@@ -5,13 +5,30 @@ use glb_rs::*;
 use image::imageops::overlay;
 use image::{ImageBuffer, RgbaImage};
 
-const EXPORT_FOLDER: &'static str = "./export";
+const EXPORT_FOLDER: &str = "./export";
 
 pub fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|a| a.as_str()) {
+        Some("export") => export(),
+        Some("diff") if args.len() == 4 => print_diff(&args[2], &args[3]),
+        Some("diff") => {
+            eprintln!("usage: main diff OLD.GLB NEW.GLB");
+            std::process::exit(2);
+        }
+        _ => measure(),
+    }
+}
+
+fn print_diff(old_path: &str, new_path: &str) {
+    let old = GlbArchive::from_file(old_path).unwrap();
+    let new = GlbArchive::from_file(new_path).unwrap();
 
+    let changes = diff(&old, &new).unwrap();
 
-    //export();
-    measure();
+    for change in &changes.changes {
+        println!("{}", change);
+    }
 }
 
 fn measure() {
@@ -19,9 +36,9 @@ fn measure() {
     for _ in 0..10 {
         let now = Instant::now();
 
-        let mut archive = GlbArchive::from_file("test_files/FILE0001.GLB").unwrap();
-        let fat = archive.parse_fat();
-        let files = archive.extract_files(&fat);
+        let archive = GlbArchive::from_file("test_files/FILE0001.GLB").unwrap();
+        let fat = archive.parse_fat().unwrap();
+        let _files = archive.extract_files(&fat).unwrap();
 
         let elapsed = now.elapsed();
         println!("Elapsed: {:.2?}", elapsed);
@@ -34,29 +51,27 @@ fn export() {
     let _ = std::fs::remove_dir_all(EXPORT_FOLDER);
     let _ = std::fs::create_dir_all(EXPORT_FOLDER);
 
-    let palette = {
-        let mut archive =  GlbArchive::from_file("test_files/FILE0001.GLB").unwrap();
-        let fat = archive.parse_fat();
-        let files = archive.extract_files(&fat);
-
-        let palette = files.named_files.get("PALETTE_DAT").unwrap().clone();
+    let archive = GlbArchive::from_file("test_files/FILE0001.GLB").unwrap();
 
-        match palette {
-            File::Palette(p) => p,
-            _ => panic!("PALETTE_DAT has to be palette!"),
-        }
+    let palette = match archive.get_file("PALETTE_DAT").unwrap() {
+        Some(File::Palette(p)) => p,
+        _ => panic!("PALETTE_DAT has to be palette!"),
     };
 
-    let mut archive = GlbArchive::from_file("test_files/FILE0001.GLB").unwrap();
-    let fat = archive.parse_fat();
-    let extracted = archive.extract_files(&fat);
+    let fat = archive.parse_fat().unwrap();
+    let extracted = archive.extract_files(&fat).unwrap();
+
+    let tiles = &extracted.tiles;
 
-    let tiles = extracted.tiles;
+    // Tiles are unnamed, they are exported together with the maps.
+    let named_files = extracted.entries.iter()
+        .filter(|e| !e.fat_entry.filename.is_empty())
+        .map(|e| &e.file);
 
-    for file in extracted.named_files.values() {
+    for file in named_files {
         match file {
             File::Map(m) => {
-                save_map(&m, &tiles, &palette);
+                save_map(m, tiles, &palette);
             }
 
             File::Text(t) => {
@@ -125,10 +140,8 @@ fn save_map(m: &Map, tiles: &Tiles, palette: &Palette) {
 }
 
 fn save_tiles(t: &Tiles, palette: &Palette) {
-    let mut ix = 0;
-    for tile in &t.tiles {
+    for (ix, tile) in t.tiles.iter().enumerate() {
         let path = format!("{}/_tile{}.png", EXPORT_FOLDER, ix);
-        ix = ix + 1;
         save_pic(tile, palette, &path);
     }
 }